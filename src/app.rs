@@ -0,0 +1,585 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// The cargo-minimal-versions command.
+
+use std::{
+    env,
+    path::Path,
+    process::{ExitCode, ExitStatus},
+    thread,
+};
+
+use anyhow::{Context as _, Result, bail};
+
+use crate::{
+    cargo::Workspace,
+    cli::{Args, Pin, Subcommand},
+    dev_deps, fix, fs, isolated, lockfile,
+    lockfile::Lockfile,
+    manifest,
+    metadata::PackageId,
+    no_modify, process, report, restore, runner, search, term,
+};
+
+pub(crate) fn main() -> ExitCode {
+    term::init_coloring();
+    let res = try_main();
+    if process::terminating() {
+        // The signal handler restores files and exits the process after the
+        // child process is terminated.
+        loop {
+            thread::park();
+        }
+    }
+    if let Err(e) = res {
+        error!("{e:#}");
+    }
+    if term::error()
+        || term::warn() && env::var_os("CARGO_MINIMAL_VERSIONS_DENY_WARNINGS").is_some()
+    {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn try_main() -> Result<()> {
+    let Some(mut args) = Args::parse()? else { return Ok(()) };
+    restore::set_signal_handler()?;
+    let mut ws = Workspace::new(&mut args)?;
+
+    let journal = restore::journal_dir(&ws.metadata.target_directory);
+    let lock = restore::lock_path(&ws.metadata.target_directory);
+    if let Subcommand::Restore = args.subcommand {
+        let _lock = fs::FileLock::acquire(&lock, args.no_wait)?;
+        return restore::replay(&journal);
+    }
+    // The journal of a running process is not a leftover.
+    if restore::is_prepared(&journal) {
+        if !matches!(args.subcommand, Subcommand::Prepare) {
+            info!(
+                "files in this workspace have been modified by \
+                 `cargo minimal-versions prepare`; run `cargo minimal-versions restore` \
+                 to restore them"
+            );
+        }
+    } else if restore::has_journal(&journal) && !fs::FileLock::is_locked(&lock) {
+        warn!(
+            "found backup of files modified by an interrupted run in {}; \
+             run `cargo minimal-versions restore` to restore them",
+            journal.display()
+        );
+    }
+    // Prevent other runs in the same workspace from modifying or restoring
+    // files while they are modified by this run. The lock is held for the
+    // whole run, including all members with --per-package-lockfile.
+    // --isolated never modifies files in the workspace, so it runs regardless
+    // of other runs.
+    let _lock = if args.dry_run || args.isolated {
+        None
+    } else {
+        Some(fs::FileLock::acquire(&lock, args.no_wait)?)
+    };
+
+    if let Subcommand::Fix = args.subcommand {
+        return fix::run(&ws.metadata, &args);
+    }
+
+    if let Subcommand::Exec = args.subcommand {
+        let normal = normal_deps(&ws, &args, args.remove_dev_deps, args.direct, None)?;
+        let mut status = None;
+        let res = manifest::with(&ws, &args, args.remove_dev_deps, None, || {
+            update(&ws, &args, args.direct, normal.as_ref())?;
+            let mut program = cmd!(&args.rest[0]);
+            program.args(&args.rest[1..]);
+            if args.dry_run {
+                program.display_env_vars();
+                println!("{program:#}");
+                return Ok(());
+            }
+            info!("running {program}");
+            let s = program.status()?;
+            status = Some(s);
+            if s.success() {
+                Ok(())
+            } else {
+                bail!("process didn't exit successfully: {program} ({s})")
+            }
+        });
+        // Propagate the exit status of the program after files are restored.
+        if let (Err(e), Some(status)) = (&res, status) {
+            error!("{e:#}");
+            std::process::exit(exit_code(status));
+        }
+        return res;
+    }
+
+    if let Subcommand::Prepare = args.subcommand {
+        // Apply the same changes as other subcommands, but leave them in place
+        // until `cargo minimal-versions restore`.
+        let normal = normal_deps(&ws, &args, args.remove_dev_deps, args.direct, None)?;
+        return manifest::with(&ws, &args, args.remove_dev_deps, None, || {
+            update(&ws, &args, args.direct, normal.as_ref())?;
+            if !args.dry_run {
+                restore::keep_all()?;
+                info!(
+                    "Cargo.toml and Cargo.lock have been modified for minimal versions check; \
+                     run `cargo minimal-versions restore` to restore them"
+                );
+            }
+            Ok(())
+        });
+    }
+
+    // Run everything in a copy of the workspace, to never modify the user's checkout.
+    let _tmp = if args.isolated { isolated::enter(&mut ws, &mut args)? } else { None };
+
+    if let Subcommand::DevDeps = args.subcommand {
+        // Resolve minimal versions with and without dev-dependencies, and
+        // compare them.
+        let lockfile_path = &ws.metadata.workspace_root.join("Cargo.lock");
+        let mut normal = None;
+        info!("resolving minimal versions without dev-dependencies");
+        manifest::resolve_only(&ws, &args, true, None, || {
+            resolve(&ws, &args, args.direct)?;
+            normal = Some(Lockfile::new(lockfile_path)?);
+            Ok(())
+        })?;
+        let mut graph = None;
+        info!("resolving minimal versions with dev-dependencies");
+        manifest::resolve_only(&ws, &args, false, None, || {
+            resolve(&ws, &args, args.direct)?;
+            graph = Some(dev_deps::Graph::new(&ws)?);
+            Ok(())
+        })?;
+        dev_deps::print(&normal.unwrap(), &graph.unwrap());
+        return Ok(());
+    }
+
+    // Remove dev-dependencies from Cargo.toml to prevent the next `cargo update`
+    // from determining minimal versions based on dev-dependencies.
+    let remove_dev_deps = !args.subcommand.always_needs_dev_deps()
+        && !args.cargo_args.iter().any(|a| match &**a {
+            "--example" | "--examples" | "--test" | "--tests" | "--bench" | "--benches"
+            | "--all-targets" => true,
+            _ => {
+                a.starts_with("--example=") || a.starts_with("--test=") || a.starts_with("--bench=")
+            }
+        });
+
+    if args.two_phase && remove_dev_deps {
+        warn!(
+            "--two-phase is only used when dev-dependencies are needed (e.g., by test \
+             subcommand or --all-targets); ignoring it for {} subcommand",
+            args.subcommand.as_str()
+        );
+    }
+
+    if args.no_modify {
+        match no_modify::unsupported(&ws, &args, remove_dev_deps) {
+            Some(reason) => warn!(
+                "--no-modify is not supported {reason}; \
+                 falling back to modifying Cargo.toml and Cargo.lock"
+            ),
+            None => {
+                return no_modify::with(&mut ws, &args, remove_dev_deps, |ws| {
+                    if !remove_dev_deps {
+                        update(ws, &args, args.direct, None)?;
+                    }
+                    runner::run(ws, &args)
+                });
+            }
+        }
+    }
+
+    if args.per_package_lockfile {
+        // Resolve and check each package against its own Cargo.lock.
+        for id in runner::packages(&ws.metadata, &args)? {
+            info!("resolving minimal versions for {}", ws.metadata[id].name);
+            let name = &ws.metadata[id].name;
+            let direct = args.members.get(name).and_then(|m| m.direct).unwrap_or(args.direct);
+            let normal = normal_deps(&ws, &args, remove_dev_deps, direct, Some(id))?;
+            manifest::with(&ws, &args, remove_dev_deps, Some(id), || {
+                update(&ws, &args, direct, normal.as_ref())?;
+                runner::run_packages(&ws, &args, Some(&[id]))
+            })?;
+        }
+        return Ok(());
+    }
+
+    let normal = normal_deps(&ws, &args, remove_dev_deps, args.direct, None)?;
+    manifest::with(&ws, &args, remove_dev_deps, None, || {
+        update(&ws, &args, args.direct, normal.as_ref())?;
+        if let Subcommand::Search(..) = args.subcommand {
+            search::run(&ws, &args)
+        } else {
+            runner::run(&ws, &args)
+        }
+    })
+}
+
+/// Returns the exit code to propagate the given exit status.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        // 128 + signal number, as shells do.
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Resolves minimal versions with dev-dependencies removed, and returns the
+/// resulting Cargo.lock to force the versions of normal dependencies after
+/// resolving with dev-dependencies (--two-phase).
+///
+/// Returns `None` if dev-dependencies are removed anyway.
+fn normal_deps(
+    ws: &Workspace,
+    args: &Args,
+    remove_dev_deps: bool,
+    direct: bool,
+    only_member: Option<PackageId>,
+) -> Result<Option<Lockfile>> {
+    if !args.two_phase || remove_dev_deps {
+        return Ok(None);
+    }
+    info!("resolving minimal versions of normal dependencies without dev-dependencies");
+    let mut normal = Lockfile { packages: vec![] };
+    manifest::resolve_only(ws, args, true, only_member, || {
+        resolve(ws, args, direct)?;
+        if !args.dry_run {
+            normal = Lockfile::new(&ws.metadata.workspace_root.join("Cargo.lock"))?;
+        }
+        Ok(())
+    })?;
+    Ok(Some(normal))
+}
+
+/// Updates Cargo.lock to minimal version dependencies, and applies --pin,
+/// --two-phase, and --report.
+fn update(ws: &Workspace, args: &Args, direct: bool, normal: Option<&Lockfile>) -> Result<()> {
+    resolve(ws, args, direct)?;
+    if let Some(normal) = normal {
+        force_normal_deps(ws, args, direct, normal)?;
+    }
+    if let Some(format) = args.report {
+        if !args.dry_run {
+            report::print(&ws.metadata.workspace_root, format)?;
+        }
+    }
+    Ok(())
+}
+
+/// Updates Cargo.lock to minimal version dependencies, and applies --pin.
+fn resolve(ws: &Workspace, args: &Args, direct: bool) -> Result<()> {
+    let mut cargo = ws.cargo_nightly();
+    cargo.args(["update", "-Z", minimal_versions_flag(direct)]);
+    ws.set_lockfile(&mut cargo)?;
+    if args.dry_run {
+        cargo.display_env_vars();
+        println!("{cargo:#}");
+    } else {
+        info!("running {cargo}");
+        cargo.run()?;
+    }
+
+    // Force the given versions of packages whose minimal versions are broken (--pin).
+    let lockfile_path = &ws.metadata.workspace_root.join("Cargo.lock");
+    for pin in &args.pins {
+        let mut spec = pin.name.clone();
+        if !args.dry_run {
+            let Some(s) = pin_spec(&Lockfile::new(lockfile_path)?, pin)? else {
+                warn!("pinned package `{}` not found in Cargo.lock", pin.name);
+                continue;
+            };
+            spec = s;
+        }
+        let mut cargo = ws.cargo_nightly();
+        cargo.args(["update", "-Z", minimal_versions_flag(direct)]);
+        cargo.args(["-p", &spec, "--precise", &pin.version.to_string()]);
+        if args.dry_run {
+            cargo.display_env_vars();
+            println!("{cargo:#}");
+            continue;
+        }
+        match &pin.reason {
+            Some(reason) => info!("pinning {} to {}: {reason}", pin.name, pin.version),
+            None => info!("pinning {} to {}", pin.name, pin.version),
+        }
+        info!("running {cargo}");
+        cargo.run()?;
+    }
+    Ok(())
+}
+
+/// Returns the package ID spec to update for the given --pin, or `None` if the
+/// package is not in the lockfile.
+///
+/// When multiple versions of the package are locked, the version to update
+/// must be the only one semver-compatible with the pinned version.
+fn pin_spec(lockfile: &Lockfile, pin: &Pin) -> Result<Option<String>> {
+    let versions: Vec<_> = lockfile.versions(&pin.name).collect();
+    match &*versions {
+        [] => Ok(None),
+        [_] => Ok(Some(pin.name.clone())),
+        _ => {
+            let compatible: Vec<_> = versions
+                .iter()
+                .filter(|v| lockfile::is_semver_compatible(v, &pin.version))
+                .collect();
+            let [version] = &*compatible else {
+                bail!(
+                    "cannot pin {} to {}: Cargo.lock contains versions {} of `{}`, and {} \
+                     semver-compatible with the pinned version",
+                    pin.name,
+                    pin.version,
+                    versions.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+                    pin.name,
+                    if compatible.is_empty() {
+                        "none of them is"
+                    } else {
+                        "more than one of them is"
+                    },
+                );
+            };
+            Ok(Some(format!("{}@{version}", pin.name)))
+        }
+    }
+}
+
+/// Forces the versions of normal dependencies that were raised by
+/// dev-dependencies back to the versions resolved without dev-dependencies
+/// (--two-phase).
+fn force_normal_deps(ws: &Workspace, args: &Args, direct: bool, normal: &Lockfile) -> Result<()> {
+    if args.dry_run {
+        info!(
+            "normal dependencies raised by dev-dependencies will be forced back to the versions \
+             resolved without dev-dependencies"
+        );
+        return Ok(());
+    }
+    let lockfile_path = &ws.metadata.workspace_root.join("Cargo.lock");
+    let mut lockfile = Lockfile::new(lockfile_path)?;
+    for package in &normal.packages {
+        let version = &package.version;
+        if lockfile.versions(&package.name).any(|v| v == version) {
+            continue;
+        }
+        let Some(raised) = lockfile
+            .versions(&package.name)
+            .find(|&v| v > version && lockfile::is_semver_compatible(v, version))
+            .cloned()
+        else {
+            continue;
+        };
+        info!(
+            "forcing {} back to {version} (raised to {raised} by dev-dependencies)",
+            package.name
+        );
+        if !precise(ws, direct, lockfile_path, &package.name, &raised, version)? {
+            // Cargo leaves the version raised without an error if a locked
+            // dev-dependency requires it, so update the dev-dependencies that
+            // depend on it to versions compatible with the normal version.
+            let dependents = dev_dependents(&lockfile, normal, &package.name);
+            let forced = !dependents.is_empty()
+                && update_dev_dependents(ws, direct, &lockfile, package, &raised, &dependents)?;
+            if !forced {
+                warn!(
+                    "failed to force {} back to {version}; {raised} required by dev-dependencies \
+                     is used instead",
+                    package.name
+                );
+            }
+        }
+        lockfile = Lockfile::new(lockfile_path)?;
+    }
+    Ok(())
+}
+
+/// Returns the package ID specs of the packages that depend on the given
+/// package and are not in `normal`, i.e., that are only used by
+/// dev-dependencies.
+fn dev_dependents(lockfile: &Lockfile, normal: &Lockfile, name: &str) -> Vec<String> {
+    lockfile
+        .packages
+        .iter()
+        .filter(|p| {
+            p.dependencies.iter().any(|d| d == name)
+                && !normal.packages.iter().any(|n| n.name == p.name && n.version == p.version)
+        })
+        .map(|p| format!("{}@{}", p.name, p.version))
+        .collect()
+}
+
+/// Updates the given dev-dependencies with minimal versions, while a temporary
+/// dev-dependency of a workspace member requires the normal version of the
+/// package raised by them, and returns whether the package was downgraded.
+///
+/// Without the temporary dev-dependency, the dev-dependencies would be updated
+/// to the same minimal versions that raised the package.
+fn update_dev_dependents(
+    ws: &Workspace,
+    direct: bool,
+    lockfile: &Lockfile,
+    package: &lockfile::Package,
+    raised: &semver::Version,
+    dependents: &[String],
+) -> Result<bool> {
+    // The temporary dependency is resolved from crates.io.
+    if !lockfile
+        .packages
+        .iter()
+        .any(|p| p.name == package.name && p.version == *raised && p.is_crates_io())
+    {
+        return Ok(false);
+    }
+    // A member that is not removed from the workspace.
+    let Some(member) = ws.metadata.workspace_members.iter().map(|&id| &ws.metadata[id]).find(|m| {
+        lockfile.packages.iter().any(|p| p.name == m.name && p.version.to_string() == m.version)
+    }) else {
+        return Ok(false);
+    };
+    let manifest_path = &*member.manifest_path;
+    let orig = fs::read_to_string(manifest_path)?;
+    let mut doc: toml_edit::DocumentMut = orig.parse().with_context(|| {
+        format!("failed to parse manifest `{}` as toml", manifest_path.display())
+    })?;
+    let mut dep = toml_edit::InlineTable::new();
+    dep.insert("package", package.name.as_str().into());
+    dep.insert("version", format!("={}", package.version).into());
+    doc.entry("dev-dependencies")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .context("failed to parse `dev-dependencies` field from manifest")?
+        .insert(&format!("cargo-minimal-versions-pin-{}", package.name), toml_edit::value(dep));
+
+    let pin = restore::Manager::new();
+    pin.write(manifest_path, orig, doc.to_string().as_bytes())?;
+    let mut cargo = ws.cargo_nightly();
+    cargo.args(["update", "-Z", minimal_versions_flag(direct)]);
+    cargo.args(["-p", &format!("{}@{raised}", package.name)]);
+    for spec in dependents {
+        cargo.args(["-p", spec]);
+    }
+    info!("running {cargo}");
+    let res = cargo.run_with_output();
+    // Remove the temporary dev-dependency.
+    drop(pin);
+    match res {
+        Ok(_) => {}
+        Err(e) if process::terminating() => return Err(e),
+        Err(e) => {
+            warn!("{e:#}");
+            return Ok(false);
+        }
+    }
+    let lockfile_path = &ws.metadata.workspace_root.join("Cargo.lock");
+    Ok(Lockfile::new(lockfile_path)?.versions(&package.name).any(|v| *v == package.version))
+}
+
+/// Runs `cargo update --precise` to downgrade the given package from `from` to
+/// `to`, and returns whether it was actually downgraded.
+fn precise(
+    ws: &Workspace,
+    direct: bool,
+    lockfile_path: &Path,
+    name: &str,
+    from: &semver::Version,
+    to: &semver::Version,
+) -> Result<bool> {
+    let mut cargo = ws.cargo_nightly();
+    cargo.args(["update", "-Z", minimal_versions_flag(direct)]);
+    cargo.args(["-p", &format!("{name}@{from}"), "--precise", &to.to_string()]);
+    info!("running {cargo}");
+    match cargo.run_with_output() {
+        Ok(_) => {}
+        Err(e) if process::terminating() => return Err(e),
+        Err(e) => {
+            if term::verbose() {
+                info!("{e:#}");
+            }
+            return Ok(false);
+        }
+    }
+    Ok(Lockfile::new(lockfile_path)?.versions(name).any(|v| v == to))
+}
+
+pub(crate) fn minimal_versions_flag(direct: bool) -> &'static str {
+    if direct { "direct-minimal-versions" } else { "minimal-versions" }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write as _, path::Path};
+
+    use super::{dev_dependents, exit_code, pin_spec};
+    use crate::{cli::Pin, lockfile::Lockfile};
+
+    fn lockfile(packages: &[(&str, &str, &[&str])]) -> Lockfile {
+        let mut raw = String::new();
+        for (name, version, deps) in packages {
+            writeln!(raw, "[[package]]\nname = \"{name}\"\nversion = \"{version}\"").unwrap();
+            writeln!(raw, "dependencies = {deps:?}").unwrap();
+        }
+        Lockfile::from_str(&raw, Path::new("Cargo.lock")).unwrap()
+    }
+
+    #[test]
+    fn dev_dependents_of_raised() {
+        let normal = lockfile(&[
+            ("ws", "0.1.0", &["foo", "baz"]),
+            ("foo", "1.0.0", &[]),
+            ("baz", "1.0.0", &["foo"]),
+        ]);
+        let lockfile = lockfile(&[
+            ("ws", "0.1.0", &["bar", "baz", "foo", "qux"]),
+            ("foo", "1.0.5", &[]),
+            ("bar", "1.0.0", &["foo 1.0.5"]),
+            ("baz", "1.0.0", &["foo"]),
+            ("qux", "0.2.0", &["bar"]),
+            ("qux", "0.1.0", &[]),
+        ]);
+        // Workspace members and normal dependencies are not updated.
+        assert_eq!(dev_dependents(&lockfile, &normal, "foo"), ["bar@1.0.0"]);
+        assert_eq!(dev_dependents(&lockfile, &normal, "bar"), ["qux@0.2.0"]);
+        assert_eq!(dev_dependents(&lockfile, &normal, "qux"), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn pin_specs() {
+        let pin = |spec: &str| Pin::parse(spec).unwrap();
+        let lockfile = lockfile(&[
+            ("foo", "1.0.0", &[]),
+            ("bar", "0.1.0", &[]),
+            ("bar", "0.2.3", &[]),
+            ("bar", "1.0.0", &[]),
+        ]);
+        assert_eq!(pin_spec(&lockfile, &pin("foo@1.2.0")).unwrap().unwrap(), "foo");
+        // A single locked version is updated even if it is semver-incompatible.
+        assert_eq!(pin_spec(&lockfile, &pin("foo@2.0.0")).unwrap().unwrap(), "foo");
+        assert_eq!(pin_spec(&lockfile, &pin("bar@0.2.1")).unwrap().unwrap(), "bar@0.2.3");
+        assert_eq!(pin_spec(&lockfile, &pin("bar@1.1.0")).unwrap().unwrap(), "bar@1.0.0");
+        assert!(pin_spec(&lockfile, &pin("baz@1.0.0")).unwrap().is_none());
+        let err = pin_spec(&lockfile, &pin("bar@0.3.0")).unwrap_err().to_string();
+        assert!(err.contains("0.1.0, 0.2.3, 1.0.0"), "{err}");
+        assert!(err.contains("none of them is semver-compatible"), "{err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_codes() {
+        use std::{os::unix::process::ExitStatusExt as _, process::ExitStatus};
+
+        // The raw value is a wait status: the exit code is in the second
+        // byte, and the signal number is in the lower 7 bits.
+        assert_eq!(exit_code(ExitStatus::from_raw(0)), 0);
+        assert_eq!(exit_code(ExitStatus::from_raw(1 << 8)), 1);
+        assert_eq!(exit_code(ExitStatus::from_raw(101 << 8)), 101);
+        assert_eq!(exit_code(ExitStatus::from_raw(2)), 130); // SIGINT
+        assert_eq!(exit_code(ExitStatus::from_raw(9)), 137); // SIGKILL
+        assert_eq!(exit_code(ExitStatus::from_raw(15)), 143); // SIGTERM
+    }
+}
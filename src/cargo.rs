@@ -1,15 +1,49 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use std::{env, ffi::OsStr, path::PathBuf};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use anyhow::{Result, format_err};
+use anyhow::{Result, bail, format_err};
 
-use crate::{metadata, process::ProcessBuilder};
+use crate::{cli::Args, config, metadata, process::ProcessBuilder};
 
-pub(crate) struct Workspace {
+/// A cargo workspace, and the cargo to use for it.
+pub struct Workspace {
     pub(crate) metadata: metadata::Metadata,
     cargo: PathBuf,
+    cargo_version: u32,
     cargo_mode: CargoMode,
+    /// The target directory to use instead of the default one (--isolated).
+    pub(crate) target_dir: Option<PathBuf>,
+    /// Cargo features used by --no-modify, or `None` if --no-modify is not
+    /// passed or the current cargo is not nightly.
+    pub(crate) no_modify: Option<NoModifySupport>,
+    /// The lockfile to use instead of Cargo.lock in the workspace root
+    /// (--no-modify).
+    pub(crate) lockfile: Option<PathBuf>,
+    /// The `-Z` flag to resolve minimal versions in the build step, with
+    /// `-Z avoid-dev-deps` (--no-modify).
+    pub(crate) avoid_dev_deps: Option<&'static str>,
+}
+
+pub(crate) struct NoModifySupport {
+    pub(crate) lockfile_path: Option<LockfilePath>,
+    pub(crate) avoid_dev_deps: bool,
+}
+
+/// How to make cargo use a lockfile other than Cargo.lock in the workspace root.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LockfilePath {
+    /// `-Z unstable-options --lockfile-path <PATH>`
+    Flag,
+    /// `resolver.lockfile-path` config with `-Z lockfile-path`
+    UnstableConfig,
+    /// `resolver.lockfile-path` config
+    Config,
 }
 
 enum CargoMode {
@@ -19,12 +53,38 @@ enum CargoMode {
 }
 
 impl Workspace {
-    pub(crate) fn new(manifest_path: Option<&str>, direct: bool) -> Result<Self> {
+    /// Loads the workspace, and applies the project config to `args`.
+    pub(crate) fn new(args: &mut Args) -> Result<Self> {
         let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
         let cargo_version = cargo_version(&cargo)?;
 
+        let metadata =
+            metadata::Metadata::new(args.manifest_path.as_deref(), &cargo, cargo_version.minor)?;
+        config::apply(args, &metadata)?;
+        let direct = args.direct || args.members.values().any(|m| m.direct == Some(true));
+        Ok(Self::with_metadata(cargo, &cargo_version, metadata, direct, args.no_modify))
+    }
+
+    /// Loads the workspace of the given manifest (or the current directory if
+    /// `manifest_path` is `None`), and detects how to run cargo with
+    /// `-Z minimal-versions`, or `-Z direct-minimal-versions` if `direct` is
+    /// `true`.
+    ///
+    /// This respects the `CARGO` environment variable.
+    pub fn load(manifest_path: Option<&str>, direct: bool) -> Result<Self> {
+        let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let cargo_version = cargo_version(&cargo)?;
         let metadata = metadata::Metadata::new(manifest_path, &cargo, cargo_version.minor)?;
+        Ok(Self::with_metadata(cargo, &cargo_version, metadata, direct, false))
+    }
 
+    fn with_metadata(
+        cargo: OsString,
+        cargo_version: &CargoVersion,
+        metadata: metadata::Metadata,
+        direct: bool,
+        no_modify: bool,
+    ) -> Self {
         let cargo_mode = if cargo_version.nightly {
             CargoMode::Nightly
         } else if !direct
@@ -42,11 +102,123 @@ impl Workspace {
             CargoMode::StableNoUnstableOption
         };
 
-        Ok(Self { cargo: cargo.into(), cargo_mode, metadata })
+        // --no-modify uses unstable features also in the build step, which
+        // is run with the current cargo.
+        let no_modify = if no_modify && matches!(cargo_mode, CargoMode::Nightly) {
+            Some(NoModifySupport::probe(&cargo, &metadata))
+        } else {
+            None
+        };
+
+        Self {
+            cargo: cargo.into(),
+            cargo_version: cargo_version.minor,
+            cargo_mode,
+            metadata,
+            target_dir: None,
+            no_modify,
+            lockfile: None,
+            avoid_dev_deps: None,
+        }
+    }
+
+    /// Returns the root directory of the workspace.
+    #[must_use]
+    pub fn workspace_root(&self) -> &Path {
+        &self.metadata.workspace_root
+    }
+
+    /// Returns the target directory of the workspace.
+    #[must_use]
+    pub fn target_directory(&self) -> &Path {
+        &self.metadata.target_directory
+    }
+
+    /// Returns the path to which Cargo.lock with minimal versions is saved
+    /// when the build fails.
+    ///
+    /// With --isolated, this is outside of the copy of the workspace, which is
+    /// removed after running.
+    pub(crate) fn failed_lockfile_path(&self) -> PathBuf {
+        match &self.target_dir {
+            Some(target_dir) => target_dir.join("Cargo.lock"),
+            None => self.metadata.target_directory.join("minimal-versions/Cargo.lock"),
+        }
+    }
+
+    /// Returns a command to run cargo.
+    #[must_use]
+    pub fn cargo_command(&self) -> Command {
+        self.cargo().into_std()
+    }
+
+    /// Returns a command to run cargo that accepts `-Z minimal-versions` and
+    /// `-Z direct-minimal-versions`.
+    ///
+    /// This is the current cargo if it is nightly or the unstable flag is
+    /// available with `RUSTC_BOOTSTRAP=1`, and nightly cargo via rustup
+    /// otherwise.
+    #[must_use]
+    pub fn cargo_nightly_command(&self) -> Command {
+        self.cargo_nightly().into_std()
+    }
+
+    /// Re-runs `cargo metadata` with the given manifest path.
+    pub(crate) fn reload_metadata(&mut self, manifest_path: Option<&str>) -> Result<()> {
+        self.metadata =
+            metadata::Metadata::new(manifest_path, self.cargo.as_os_str(), self.cargo_version)?;
+        Ok(())
     }
 
     pub(crate) fn cargo(&self) -> ProcessBuilder {
-        cmd!(&self.cargo)
+        let mut cargo = cmd!(&self.cargo);
+        self.set_env(&mut cargo);
+        cargo
+    }
+
+    /// Returns `cargo` of the given toolchain, used for the build step of
+    /// `--rust-version`.
+    // Do not use `cargo +<toolchain>` due to a rustup bug: https://github.com/rust-lang/rustup/issues/3036
+    pub(crate) fn cargo_toolchain(&self, toolchain: &str) -> ProcessBuilder {
+        let mut cargo = cmd!("rustup", "run", toolchain, "cargo");
+        self.set_env(&mut cargo);
+        cargo
+    }
+
+    fn set_env(&self, cargo: &mut ProcessBuilder) {
+        if let Some(target_dir) = &self.target_dir {
+            cargo.env("CARGO_TARGET_DIR", target_dir);
+        }
+    }
+
+    /// Makes the given cargo command use `self.lockfile` and resolve minimal
+    /// versions without dev-dependencies if needed (--no-modify).
+    ///
+    /// This must be called after the cargo subcommand is added.
+    pub(crate) fn set_lockfile(&self, cargo: &mut ProcessBuilder) -> Result<()> {
+        let Some(lockfile) = &self.lockfile else { return Ok(()) };
+        match self.no_modify.as_ref().and_then(|n| n.lockfile_path) {
+            Some(LockfilePath::Flag) => {
+                cargo.args(["-Z", "unstable-options", "--lockfile-path"]);
+                cargo.arg(lockfile);
+            }
+            Some(LockfilePath::UnstableConfig) => {
+                cargo.env("CARGO_UNSTABLE_LOCKFILE_PATH", "true");
+                cargo.env("CARGO_RESOLVER_LOCKFILE_PATH", lockfile);
+            }
+            Some(LockfilePath::Config) => {
+                cargo.env("CARGO_RESOLVER_LOCKFILE_PATH", lockfile);
+            }
+            None => bail!(
+                "the current cargo does not support using a lockfile other than Cargo.lock in \
+                 the workspace root"
+            ),
+        }
+        if let Some(flag) = self.avoid_dev_deps {
+            cargo.env("CARGO_UNSTABLE_AVOID_DEV_DEPS", "true");
+            cargo.env(format!("CARGO_UNSTABLE_{}", flag.replace('-', "_").to_uppercase()), "true");
+        }
+        Ok(())
     }
 
     // Used for `cargo update -Z minimal-versions` / `cargo update -Z direct-minimal-versions`
@@ -59,7 +231,56 @@ impl Workspace {
                 cargo
             }
             // Do not use `cargo +nightly` due to a rustup bug: https://github.com/rust-lang/rustup/issues/3036
-            CargoMode::StableNoUnstableOption => cmd!("rustup", "run", "nightly", "cargo"),
+            CargoMode::StableNoUnstableOption => {
+                let mut cargo = cmd!("rustup", "run", "nightly", "cargo");
+                self.set_env(&mut cargo);
+                cargo
+            }
+        }
+    }
+}
+
+impl NoModifySupport {
+    fn probe(cargo: &OsStr, metadata: &metadata::Metadata) -> Self {
+        let z_help = cmd!(cargo, "-Z", "help").read().unwrap_or_default();
+        let lockfile_path = LockfilePath::detect(
+            &z_help,
+            || cmd!(cargo, "update", "--help").read().unwrap_or_default(),
+            || {
+                let mut cmd =
+                    cmd!(cargo, "metadata", "--format-version=1", "--no-deps", "--offline");
+                cmd.arg("--manifest-path");
+                cmd.arg(metadata.workspace_root.join("Cargo.toml"));
+                cmd.env(
+                    "CARGO_RESOLVER_LOCKFILE_PATH",
+                    metadata.target_directory.join("minimal-versions/probe.lock"),
+                );
+                cmd.run_with_output().err().map(|e| format!("{e:#}"))
+            },
+        );
+        Self { lockfile_path, avoid_dev_deps: z_help.contains("avoid-dev-deps") }
+    }
+}
+
+impl LockfilePath {
+    /// Detects how to use a lockfile at another path from the output of
+    /// `cargo -Z help`, `cargo update --help`, and the error of
+    /// `cargo metadata` run with `resolver.lockfile-path` set to a path that
+    /// is not named Cargo.lock.
+    fn detect(
+        z_help: &str,
+        update_help: impl FnOnce() -> String,
+        probe_error: impl FnOnce() -> Option<String>,
+    ) -> Option<Self> {
+        if z_help.contains("lockfile-path") {
+            Some(Self::UnstableConfig)
+        } else if update_help().contains("--lockfile-path") {
+            Some(Self::Flag)
+        } else {
+            // `resolver.lockfile-path` has been stabilized if cargo rejects a
+            // path that is not named Cargo.lock. Older cargo ignores the
+            // unknown config and succeeds.
+            probe_error().filter(|e| e.contains("resolver.lockfile-path")).map(|_| Self::Config)
         }
     }
 }
@@ -95,3 +316,92 @@ impl CargoVersion {
         Some(Self { minor, nightly })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use serde_json::json;
+
+    use super::{CargoMode, LockfilePath, NoModifySupport, Workspace};
+    use crate::metadata::Metadata;
+
+    fn workspace(lockfile_path: Option<LockfilePath>) -> Workspace {
+        let metadata = Metadata::from_json(&json!({
+            "packages": [],
+            "workspace_members": [],
+            "workspace_root": "/ws",
+            "target_directory": "/ws/target",
+        }));
+        Workspace {
+            metadata,
+            cargo: "cargo".into(),
+            cargo_version: u32::MAX,
+            cargo_mode: CargoMode::Nightly,
+            target_dir: None,
+            no_modify: Some(NoModifySupport { lockfile_path, avoid_dev_deps: false }),
+            lockfile: Some("/ws/target/minimal-versions/Cargo.lock".into()),
+            avoid_dev_deps: None,
+        }
+    }
+
+    #[test]
+    fn set_lockfile() {
+        let lockfile = OsStr::new("/ws/target/minimal-versions/Cargo.lock");
+
+        let ws = workspace(Some(LockfilePath::Flag));
+        let mut cargo = ws.cargo();
+        cargo.arg("update");
+        ws.set_lockfile(&mut cargo).unwrap();
+        let cargo = cargo.into_std();
+        assert_eq!(cargo.get_args().collect::<Vec<_>>(), [
+            OsStr::new("update"),
+            OsStr::new("-Z"),
+            OsStr::new("unstable-options"),
+            OsStr::new("--lockfile-path"),
+            lockfile
+        ]);
+
+        let ws = workspace(Some(LockfilePath::Config));
+        let mut cargo = ws.cargo();
+        cargo.arg("update");
+        ws.set_lockfile(&mut cargo).unwrap();
+        let cargo = cargo.into_std();
+        assert_eq!(cargo.get_args().collect::<Vec<_>>(), ["update"]);
+        assert_eq!(cargo.get_envs().collect::<Vec<_>>(), [(
+            OsStr::new("CARGO_RESOLVER_LOCKFILE_PATH"),
+            Some(lockfile)
+        )]);
+
+        let ws = workspace(None);
+        let mut cargo = ws.cargo();
+        cargo.arg("update");
+        assert!(ws.set_lockfile(&mut cargo).is_err());
+    }
+
+    #[test]
+    fn detect_lockfile_path() {
+        let detect = |z_help: &str, update_help: &str, probe_error: Option<&str>| {
+            LockfilePath::detect(
+                z_help,
+                || update_help.to_owned(),
+                || probe_error.map(str::to_owned),
+            )
+        };
+        let z_help =
+            "    -Z lockfile-path          Enable the `resolver.lockfile-path` config option";
+        let update_help = "      --lockfile-path <PATH>  Path to Cargo.lock (unstable)";
+        let probe_error = "process didn't exit successfully: `cargo metadata --format-version=1 \
+                           --no-deps --offline` (exit status: 101)\n--- stderr\nerror: the \
+                           `resolver.lockfile-path` must be a path to a Cargo.lock file\n";
+
+        assert_eq!(detect(z_help, update_help, None), Some(LockfilePath::UnstableConfig));
+        // `-Z unstable-options --lockfile-path`.
+        assert_eq!(detect("", update_help, None), Some(LockfilePath::Flag));
+        // Stabilized `resolver.lockfile-path` config.
+        assert_eq!(detect("", "", Some(probe_error)), Some(LockfilePath::Config));
+        // Unsupported: the config is ignored, or the probe failed for other reasons.
+        assert_eq!(detect("", "", None), None);
+        assert_eq!(detect("", "", Some("error: failed to parse manifest")), None);
+    }
+}
@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use std::{env, ffi::OsString};
+use std::{collections::HashMap, env, ffi::OsString};
 
 use anyhow::{Result, bail, format_err};
 use lexopt::{
@@ -8,7 +8,7 @@ use lexopt::{
     ValueExt as _,
 };
 
-use crate::term;
+use crate::{config::MemberConfig, term};
 
 static USAGE: &str = "cargo-minimal-versions\n
 Cargo subcommand for proper use of -Z minimal-versions and -Z direct-minimal-versions.
@@ -19,6 +19,21 @@ Cargo subcommand for proper use of -Z minimal-versions and -Z direct-minimal-ver
     check
     test
     ...
+\nOTHER SUBCOMMANDS:
+    fix <DEPENDENCY>... | --all
+            Raise version requirements of direct dependencies to the versions in Cargo.lock
+    search <CARGO_SUBCOMMAND> [OPTIONS] [CARGO_OPTIONS]
+            Find the lowest version of each direct dependency that actually compiles
+    exec -- <PROGRAM> [ARGS]...
+            Run the given program with manifests and Cargo.lock modified for minimal versions
+    dev-deps
+            List normal dependencies whose minimal versions are raised by dev-dependencies,
+            and the dev-dependencies that raise them
+    prepare
+            Modify manifests and update Cargo.lock to minimal versions, and leave them in place
+    restore
+            Restore manifests and Cargo.lock modified by `prepare` or left modified by an
+            interrupted run
 ";
 
 pub(crate) struct Args {
@@ -26,7 +41,44 @@ pub(crate) struct Args {
     pub(crate) direct: bool,
     pub(crate) subcommand: Subcommand,
     pub(crate) manifest_path: Option<String>,
+    /// -p, --package <SPEC>...
+    pub(crate) package: Vec<String>,
+    /// --workspace, --all
+    pub(crate) workspace: bool,
+    /// --exclude <SPEC>...
+    pub(crate) exclude: Vec<String>,
     pub(crate) detach_path_deps: Option<DetachPathDeps>,
+    /// --keep-lockfile
+    pub(crate) keep_lockfile: bool,
+    /// --lockfile-out <PATH>
+    pub(crate) lockfile_out: Option<String>,
+    /// --isolated
+    pub(crate) isolated: bool,
+    /// --per-package-lockfile
+    pub(crate) per_package_lockfile: bool,
+    /// --rust-version, --msrv
+    pub(crate) rust_version: bool,
+    /// --dry-run
+    pub(crate) dry_run: bool,
+    /// --no-wait
+    pub(crate) no_wait: bool,
+    /// --no-modify
+    pub(crate) no_modify: bool,
+    /// --remove-dev-deps, --no-dev-deps (prepare and exec subcommands)
+    pub(crate) remove_dev_deps: bool,
+    /// --two-phase
+    pub(crate) two_phase: bool,
+    /// --pin <PACKAGE>@<VERSION>...
+    pub(crate) pins: Vec<Pin>,
+    /// --report[=table|json]
+    pub(crate) report: Option<ReportFormat>,
+    /// Per-member config in [package.metadata.cargo-minimal-versions], keyed
+    /// by package name.
+    pub(crate) members: HashMap<String, MemberConfig>,
+    /// Dependencies to operate on (fix subcommand).
+    pub(crate) deps: Vec<String>,
+    /// --diff (fix subcommand)
+    pub(crate) diff: bool,
     pub(crate) cargo_args: Vec<String>,
     pub(crate) rest: Vec<String>,
 }
@@ -37,6 +89,18 @@ pub(crate) enum Subcommand {
     // test, bench
     BuiltinDev(String),
     Other(String),
+    // cargo minimal-versions search <CARGO_SUBCOMMAND>
+    Search(Box<Subcommand>),
+    // cargo minimal-versions fix
+    Fix,
+    // cargo minimal-versions exec
+    Exec,
+    // cargo minimal-versions dev-deps
+    DevDeps,
+    // cargo minimal-versions prepare
+    Prepare,
+    // cargo minimal-versions restore
+    Restore,
 }
 
 impl Subcommand {
@@ -45,6 +109,11 @@ impl Subcommand {
         match s {
             "b" | "build" | "c" | "check" | "r" | "run" | "clippy" => Self::Builtin(s.to_owned()),
             "t" | "test" | "bench" => Self::BuiltinDev(s.to_owned()),
+            "fix" => Self::Fix,
+            "exec" => Self::Exec,
+            "dev-deps" => Self::DevDeps,
+            "prepare" => Self::Prepare,
+            "restore" => Self::Restore,
             _ => {
                 warn!(
                     "unrecognized subcommand '{s}'; minimal-versions check may not work as expected"
@@ -55,24 +124,69 @@ impl Subcommand {
     }
 
     pub(crate) fn always_needs_dev_deps(&self) -> bool {
-        matches!(self, Self::BuiltinDev(..))
+        match self {
+            Self::BuiltinDev(..) => true,
+            Self::Search(subcommand) => subcommand.always_needs_dev_deps(),
+            _ => false,
+        }
     }
 
     pub(crate) fn as_str(&self) -> &str {
         match self {
             Self::Builtin(s) | Self::BuiltinDev(s) | Self::Other(s) => s,
+            Self::Search(..) => "search",
+            Self::Fix => "fix",
+            Self::Exec => "exec",
+            Self::DevDeps => "dev-deps",
+            Self::Prepare => "prepare",
+            Self::Restore => "restore",
         }
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
-pub(crate) enum DetachPathDeps {
+/// Which path dependencies to detach (--detach-path-deps).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetachPathDeps {
+    /// Detach all path dependencies that have `version` or `git` field.
     All,
+    /// Same as `All`, but keep path dependencies with exact version
+    /// requirements (`=x.y.z`).
     SkipExact,
 }
 
+#[derive(Clone, Copy)]
+pub(crate) enum ReportFormat {
+    Table,
+    Json,
+}
+
+/// A package version to force after resolving minimal versions (--pin).
+pub(crate) struct Pin {
+    pub(crate) name: String,
+    pub(crate) version: semver::Version,
+    /// Why this version is pinned, shown in the output.
+    pub(crate) reason: Option<String>,
+}
+
+impl Pin {
+    /// Parses `<PACKAGE>@<VERSION>`.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let (name, version) = s.split_once('@')?;
+        if name.is_empty() {
+            return None;
+        }
+        Some(Self { name: name.to_owned(), version: version.parse().ok()?, reason: None })
+    }
+}
+
 impl Args {
     pub(crate) fn parse() -> Result<Option<Self>> {
+        Self::parse_from(env::args_os())
+    }
+
+    pub(crate) fn parse_from(
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> Result<Option<Self>> {
         const SUBCMD: &str = "minimal-versions";
 
         // rustc/cargo args must be valid Unicode
@@ -88,7 +202,7 @@ impl Args {
             })
         }
 
-        let mut raw_args = handle_args(env::args_os());
+        let mut raw_args = handle_args(args);
         raw_args.next(); // cargo
         match raw_args.next().transpose()? {
             Some(a) if a == SUBCMD => {}
@@ -109,11 +223,30 @@ impl Args {
         let mut subcommand = None;
         let mut color = None;
         let mut manifest_path: Option<String> = None;
+        let mut lockfile_out: Option<String> = None;
         let mut verbose = 0;
         let mut detach_path_deps = None;
+        let mut package = vec![];
+        let mut deps = vec![];
+        let mut exclude = vec![];
+        let mut pins = vec![];
+        let mut report = None;
 
+        let mut workspace = false;
         let mut direct = false;
         let mut no_private = false;
+        let mut keep_lockfile = false;
+        let mut isolated = false;
+        let mut per_package_lockfile = false;
+        let mut rust_version = false;
+        let mut dry_run = false;
+        let mut no_wait = false;
+        let mut no_modify = false;
+        let mut remove_dev_deps = false;
+        let mut two_phase = false;
+        let mut all = false;
+        let mut diff = false;
+        let mut search = false;
 
         let mut parser = lexopt::Parser::from_args(args);
         while let Some(arg) = parser.next()? {
@@ -138,6 +271,14 @@ impl Args {
                 Long("color") => parse_opt!(color),
                 Long("manifest-path") => parse_opt!(manifest_path),
                 Short('v') | Long("verbose") => verbose += 1,
+                // Package selection flags are handled by cargo-minimal-versions
+                // because the build step is run for each package.
+                Short('p') | Long("package") => package.push(parser.value()?.parse()?),
+                Long("exclude") => exclude.push(parser.value()?.parse()?),
+                // fix subcommand always operates on all members, and uses
+                // --all for all dependencies.
+                Long("all") if matches!(subcommand, Some(Subcommand::Fix)) => parse_flag!(all),
+                Long("workspace" | "all") => parse_flag!(workspace),
                 Long("detach-path-deps") => {
                     if let Some(val) = parser.optional_value() {
                         if val == "all" {
@@ -156,11 +297,46 @@ impl Args {
                 }
 
                 Long("direct") => parse_flag!(direct),
+                Long("keep-lockfile") => parse_flag!(keep_lockfile),
+                Long("lockfile-out") => parse_opt!(lockfile_out),
+                Long("isolated") => parse_flag!(isolated),
+                Long("per-package-lockfile") => parse_flag!(per_package_lockfile),
+                Long("rust-version" | "msrv") => parse_flag!(rust_version),
+                Long("dry-run") => parse_flag!(dry_run),
+                Long("no-wait") => parse_flag!(no_wait),
+                Long("no-modify") => parse_flag!(no_modify),
+                Long("two-phase") => parse_flag!(two_phase),
+                Long("report") => {
+                    if report.is_some() {
+                        multi_arg(&arg)?;
+                    }
+                    report = match parser.optional_value() {
+                        None => Some(ReportFormat::Table),
+                        Some(val) if val == "table" => Some(ReportFormat::Table),
+                        Some(val) if val == "json" => Some(ReportFormat::Json),
+                        Some(val) => {
+                            bail!("unrecognized value for --report, must be table or json: {val:?}")
+                        }
+                    };
+                }
+                Long("pin") => {
+                    let val: String = parser.value()?.parse()?;
+                    let Some(pin) = Pin::parse(&val) else {
+                        bail!("--pin requires <PACKAGE>@<VERSION>, found {val:?}");
+                    };
+                    // Later pins of the same package take precedence.
+                    pins.retain(|p: &Pin| p.name != pin.name);
+                    pins.push(pin);
+                }
+                Long("diff") if matches!(subcommand, Some(Subcommand::Fix)) => parse_flag!(diff),
 
                 // cargo-hack flags
                 // However, do not propagate to cargo-hack, as the same process
                 // is done by cargo-minimal-versions.
-                Long("remove-dev-deps" | "no-dev-deps") => {} // TODO: warn?
+                // This is only used by prepare and exec subcommands, which cannot
+                // infer whether dev-dependencies are needed, and ignored with a
+                // warning by other subcommands.
+                Long("remove-dev-deps" | "no-dev-deps") => parse_flag!(remove_dev_deps),
                 // Turn --ignore-private into --no-private.
                 Long("ignore-private" | "no-private") => parse_flag!(no_private),
 
@@ -195,17 +371,66 @@ impl Args {
                 }
                 Value(val) => {
                     let val = val.string()?;
-                    if subcommand.is_none() {
-                        subcommand = Some(Subcommand::new(&val));
+                    match subcommand {
+                        None if val == "search" && !search => search = true,
+                        None => {
+                            let s = Subcommand::new(&val);
+                            if !matches!(
+                                s,
+                                Subcommand::Fix
+                                    | Subcommand::Exec
+                                    | Subcommand::DevDeps
+                                    | Subcommand::Prepare
+                                    | Subcommand::Restore
+                            ) {
+                                cargo_args.push(val);
+                            }
+                            subcommand = Some(s);
+                        }
+                        Some(Subcommand::Fix) => deps.push(val),
+                        Some(
+                            s @ (Subcommand::Exec
+                            | Subcommand::DevDeps
+                            | Subcommand::Prepare
+                            | Subcommand::Restore),
+                        ) => {
+                            bail!(
+                                "unexpected argument '{val}' for `cargo minimal-versions {}`",
+                                s.as_str()
+                            )
+                        }
+                        Some(_) => cargo_args.push(val),
                     }
-                    cargo_args.push(val);
                 }
             }
         }
 
         term::set_coloring(color)?;
 
-        let Some(subcommand) = subcommand else { bail!("expected subcommand") };
+        let Some(subcommand) = subcommand else {
+            if search {
+                bail!("`cargo minimal-versions search` requires a cargo subcommand");
+            }
+            bail!("expected subcommand")
+        };
+        if matches!(
+            subcommand,
+            Subcommand::Fix
+                | Subcommand::Exec
+                | Subcommand::DevDeps
+                | Subcommand::Prepare
+                | Subcommand::Restore
+        ) {
+            let name = subcommand.as_str();
+            if search {
+                bail!(
+                    "`cargo minimal-versions search` requires a cargo subcommand, but found '{name}'"
+                );
+            }
+            if let Some(arg) = cargo_args.first() {
+                bail!("unexpected argument '{arg}' for `cargo minimal-versions {name}`");
+            }
+        }
 
         term::verbose::set(verbose != 0);
         // If `-vv` is passed, propagate `-v` to cargo.
@@ -216,9 +441,101 @@ impl Args {
             cargo_args.push("--color".to_owned());
             cargo_args.push(color.as_str().to_owned());
         }
-        if let Some(path) = &manifest_path {
-            cargo_args.push("--manifest-path".to_owned());
-            cargo_args.push(path.clone());
+        let subcommand = if search { Subcommand::Search(Box::new(subcommand)) } else { subcommand };
+        if per_package_lockfile && lockfile_out.is_some() {
+            bail!("--per-package-lockfile may not be used together with --lockfile-out");
+        }
+        if let Subcommand::Search(..) = subcommand {
+            // search subcommand updates Cargo.lock in the workspace root many times.
+            if per_package_lockfile {
+                bail!("--per-package-lockfile may not be used together with search subcommand");
+            }
+            if no_modify {
+                bail!("--no-modify may not be used together with search subcommand");
+            }
+            if dry_run {
+                bail!("--dry-run may not be used together with search subcommand");
+            }
+        }
+        if let Subcommand::Exec | Subcommand::Prepare = subcommand {
+            let name = subcommand.as_str();
+            if per_package_lockfile {
+                bail!("--per-package-lockfile may not be used together with {name} subcommand");
+            }
+            if isolated {
+                bail!("--isolated may not be used together with {name} subcommand");
+            }
+            if no_modify {
+                bail!("--no-modify may not be used together with {name} subcommand");
+            }
+        }
+        if remove_dev_deps && !matches!(subcommand, Subcommand::Exec | Subcommand::Prepare) {
+            warn!(
+                "--remove-dev-deps is only used by exec and prepare subcommands; ignoring it \
+                 for {} subcommand",
+                subcommand.as_str()
+            );
+        }
+        if two_phase
+            && remove_dev_deps
+            && matches!(subcommand, Subcommand::Exec | Subcommand::Prepare)
+        {
+            warn!(
+                "--two-phase is only used when dev-dependencies are kept; ignoring it because \
+                 --remove-dev-deps is passed"
+            );
+        }
+        if let Subcommand::DevDeps = subcommand {
+            if per_package_lockfile {
+                bail!("--per-package-lockfile may not be used together with dev-deps subcommand");
+            }
+            if no_modify {
+                bail!("--no-modify may not be used together with dev-deps subcommand");
+            }
+            if dry_run {
+                bail!("--dry-run may not be used together with dev-deps subcommand");
+            }
+        }
+        if let Subcommand::Restore = subcommand {
+            if dry_run {
+                bail!("--dry-run may not be used together with restore subcommand");
+            }
+        }
+        if let Subcommand::Fix | Subcommand::Restore = subcommand {
+            // These subcommands modify files in the workspace by design.
+            if isolated {
+                bail!(
+                    "--isolated may not be used together with {} subcommand",
+                    subcommand.as_str()
+                );
+            }
+        }
+        if let Subcommand::Fix = subcommand {
+            if all && !deps.is_empty() {
+                bail!("--all may not be used together with dependency names");
+            }
+            if !all && deps.is_empty() {
+                bail!(
+                    "`cargo minimal-versions fix` requires dependencies to raise, or --all to \
+                     raise all direct dependencies"
+                );
+            }
+        }
+        match subcommand {
+            Subcommand::Exec if rest.is_empty() => {
+                bail!("`cargo minimal-versions exec` requires a program to run after `--`")
+            }
+            Subcommand::DevDeps | Subcommand::Prepare | Subcommand::Restore if !rest.is_empty() => {
+                bail!(
+                    "unexpected argument '{}' for `cargo minimal-versions {}`",
+                    rest[0],
+                    subcommand.as_str()
+                )
+            }
+            _ => {}
+        }
+        if !exclude.is_empty() && !workspace {
+            bail!("--exclude can only be used together with --workspace");
         }
 
         Ok(Some(Self {
@@ -226,7 +543,25 @@ impl Args {
             direct,
             subcommand,
             manifest_path,
+            package,
+            workspace,
+            exclude,
             detach_path_deps,
+            keep_lockfile,
+            lockfile_out,
+            isolated,
+            per_package_lockfile,
+            rust_version,
+            dry_run,
+            no_wait,
+            no_modify,
+            remove_dev_deps,
+            two_phase,
+            pins,
+            report,
+            members: HashMap::new(),
+            deps,
+            diff,
             cargo_args,
             rest,
         }))
@@ -0,0 +1,256 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Project config in [workspace.metadata.cargo-minimal-versions] and
+// [package.metadata.cargo-minimal-versions].
+
+use std::env;
+
+use anyhow::{Result, bail, format_err};
+use serde_json::{Map, Value};
+
+use crate::{
+    cli::{Args, DetachPathDeps, Pin},
+    metadata::Metadata,
+};
+
+const KEY: &str = "cargo-minimal-versions";
+
+/// Per-member config in [package.metadata.cargo-minimal-versions].
+#[derive(Default)]
+pub(crate) struct MemberConfig {
+    /// Removes this member from the workspace in the same way as private
+    /// crates with --ignore-private.
+    pub(crate) skip: bool,
+    /// Overrides --direct for this member. Always `None` without
+    /// --per-package-lockfile.
+    pub(crate) direct: Option<bool>,
+    /// Overrides --detach-path-deps for this member. `Some(None)` disables it.
+    #[allow(clippy::option_option)]
+    pub(crate) detach_path_deps: Option<Option<DetachPathDeps>>,
+}
+
+/// Applies the project config and `CARGO_MINIMAL_VERSIONS_*` environment
+/// variables to `args`.
+///
+/// Environment variables take precedence over the config, and flags passed on
+/// the command line take precedence over both.
+pub(crate) fn apply(args: &mut Args, metadata: &Metadata) -> Result<()> {
+    let mut ignore_private = None;
+    let mut direct = None;
+    let mut detach_path_deps = None;
+    let mut pins = vec![];
+    let section = "[workspace.metadata.cargo-minimal-versions]";
+    for (key, value) in table(&metadata.workspace_metadata, section)?.into_iter().flatten() {
+        match &**key {
+            "ignore-private" => ignore_private = Some(config_bool(value, key, section)?),
+            "direct" => direct = Some(config_bool(value, key, section)?),
+            "detach-path-deps" => {
+                detach_path_deps = Some(config_detach_path_deps(value, key, section)?);
+            }
+            "pin" => pins = config_pins(value, section)?,
+            _ => warn!("unused key `{key}` in {section}"),
+        }
+    }
+
+    if let Some(value) = env_bool("CARGO_MINIMAL_VERSIONS_IGNORE_PRIVATE")? {
+        ignore_private = Some(value);
+    }
+    if let Some(value) = env_bool("CARGO_MINIMAL_VERSIONS_DIRECT")? {
+        direct = Some(value);
+    }
+    let name = "CARGO_MINIMAL_VERSIONS_DETACH_PATH_DEPS";
+    if let Some(value) = env_var(name)? {
+        detach_path_deps =
+            Some(as_detach_path_deps(&Value::String(value.clone())).ok_or_else(|| {
+                format_err!(
+                    "{name} must be `all`, `skip-exact`, `true`, or `false`, found {value:?}"
+                )
+            })?);
+    }
+
+    args.no_private |= ignore_private == Some(true);
+    args.direct |= direct == Some(true);
+    if args.detach_path_deps.is_none() {
+        args.detach_path_deps = detach_path_deps.flatten();
+    }
+    pins.retain(|pin: &Pin| !args.pins.iter().any(|p| p.name == pin.name));
+    pins.append(&mut args.pins);
+    args.pins = pins;
+
+    for &id in &metadata.workspace_members {
+        let package = &metadata[id];
+        let section = &format!("[package.metadata.cargo-minimal-versions] of {}", package.name);
+        let mut config = MemberConfig::default();
+        for (key, value) in table(&package.metadata, section)?.into_iter().flatten() {
+            match &**key {
+                "skip" => config.skip = config_bool(value, key, section)?,
+                "direct" => {
+                    let direct = config_bool(value, key, section)?;
+                    // Cargo.lock is shared within the workspace, so the
+                    // per-member setting is a no-op unless each member is
+                    // resolved separately.
+                    if args.per_package_lockfile {
+                        config.direct = Some(direct);
+                    }
+                }
+                "detach-path-deps" => {
+                    config.detach_path_deps = Some(config_detach_path_deps(value, key, section)?);
+                }
+                _ => warn!("unused key `{key}` in {section}"),
+            }
+        }
+        args.members.insert(package.name.clone(), config);
+    }
+    Ok(())
+}
+
+fn table<'a>(metadata: &'a Value, section: &str) -> Result<Option<&'a Map<String, Value>>> {
+    match metadata.get(KEY) {
+        None => Ok(None),
+        Some(Value::Object(table)) => Ok(Some(table)),
+        Some(_) => bail!("{section} must be a table"),
+    }
+}
+
+fn config_bool(value: &Value, key: &str, section: &str) -> Result<bool> {
+    as_bool(value).ok_or_else(|| format_err!("`{key}` in {section} must be a boolean"))
+}
+
+fn config_detach_path_deps(
+    value: &Value,
+    key: &str,
+    section: &str,
+) -> Result<Option<DetachPathDeps>> {
+    as_detach_path_deps(value).ok_or_else(|| {
+        format_err!("`{key}` in {section} must be a boolean, \"all\", or \"skip-exact\"")
+    })
+}
+
+/// Parses [workspace.metadata.cargo-minimal-versions.pin] table, whose values
+/// are a version, or a table with `version` and optional `reason`.
+fn config_pins(value: &Value, section: &str) -> Result<Vec<Pin>> {
+    let Value::Object(table) = value else { bail!("`pin` in {section} must be a table") };
+    let mut pins = Vec::with_capacity(table.len());
+    for (name, value) in table {
+        let (version, reason) = match value {
+            Value::String(version) => (Some(version.as_str()), None),
+            Value::Object(table) => {
+                let reason = match table.get("reason") {
+                    None => None,
+                    Some(Value::String(reason)) => Some(reason.clone()),
+                    Some(_) => bail!("`pin.{name}.reason` in {section} must be a string"),
+                };
+                (table.get("version").and_then(Value::as_str), reason)
+            }
+            _ => (None, None),
+        };
+        let Some(Ok(version)) = version.map(str::parse) else {
+            bail!("`pin.{name}` in {section} must be a version or a table with `version` field");
+        };
+        pins.push(Pin { name: name.clone(), version, reason });
+    }
+    Ok(pins)
+}
+
+fn env_var(name: &str) -> Result<Option<String>> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => bail!("{name} must be valid Unicode"),
+    }
+}
+
+fn env_bool(name: &str) -> Result<Option<bool>> {
+    let Some(value) = env_var(name)? else { return Ok(None) };
+    as_bool(&Value::String(value.clone()))
+        .map(Some)
+        .ok_or_else(|| format_err!("{name} must be `true` or `false`, found {value:?}"))
+}
+
+/// Parses a boolean, or a string "true" or "false".
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::String(s) => match &**s {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Parses a boolean, "all", or "skip-exact". `true` means "all".
+#[allow(clippy::option_option)]
+fn as_detach_path_deps(value: &Value) -> Option<Option<DetachPathDeps>> {
+    match value {
+        Value::String(s) if s == "all" => Some(Some(DetachPathDeps::All)),
+        Value::String(s) if s == "skip-exact" => Some(Some(DetachPathDeps::SkipExact)),
+        _ => as_bool(value).map(|b| b.then_some(DetachPathDeps::All)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{as_bool, as_detach_path_deps, config_pins};
+    use crate::cli::DetachPathDeps;
+
+    #[test]
+    fn bool() {
+        assert_eq!(as_bool(&json!(true)), Some(true));
+        assert_eq!(as_bool(&json!("false")), Some(false));
+        assert_eq!(as_bool(&json!("true")), Some(true));
+        assert_eq!(as_bool(&Value::String(String::new())), None);
+        assert_eq!(as_bool(&json!("1")), None);
+        assert_eq!(as_bool(&json!("0")), None);
+        assert_eq!(as_bool(&json!("yes")), None);
+        assert_eq!(as_bool(&json!(1)), None);
+    }
+
+    #[test]
+    fn detach_path_deps() {
+        assert_eq!(as_detach_path_deps(&json!(true)), Some(Some(DetachPathDeps::All)));
+        assert_eq!(as_detach_path_deps(&json!("all")), Some(Some(DetachPathDeps::All)));
+        assert_eq!(
+            as_detach_path_deps(&json!("skip-exact")),
+            Some(Some(DetachPathDeps::SkipExact))
+        );
+        assert_eq!(as_detach_path_deps(&json!(false)), Some(None));
+        assert!(as_detach_path_deps(&json!("exact")).is_none());
+    }
+
+    #[test]
+    fn pins() {
+        let pins = config_pins(
+            &json!({ "foo": "1.2.3", "bar": { "version": "0.1.0", "reason": "0.1.0 is yanked" } }),
+            "workspace metadata",
+        )
+        .unwrap();
+        let pins: Vec<_> = pins
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.to_string(), p.reason.as_deref()))
+            .collect();
+        assert_eq!(pins, [
+            ("bar", "0.1.0".to_owned(), Some("0.1.0 is yanked")),
+            ("foo", "1.2.3".to_owned(), None),
+        ]);
+        for (value, err) in [
+            (json!(["foo@1.2.3"]), "`pin` in workspace metadata must be a table"),
+            (json!({ "foo": "1.2" }), "`pin.foo` in workspace metadata must be a version"),
+            (json!({ "foo": 1 }), "`pin.foo` in workspace metadata must be a version"),
+            (
+                json!({ "foo": { "reason": "x" } }),
+                "`pin.foo` in workspace metadata must be a version",
+            ),
+            (
+                json!({ "foo": { "version": "1.2.3", "reason": 1 } }),
+                "`pin.foo.reason` in workspace metadata must be a string",
+            ),
+        ] {
+            let e = config_pins(&value, "workspace metadata").err().unwrap().to_string();
+            assert!(e.starts_with(err), "{e}");
+        }
+    }
+}
@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of `cargo minimal-versions dev-deps`.
+//
+// Compares minimal versions resolved with and without dev-dependencies, and
+// finds the dev-dependencies that raise the versions of normal dependencies.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use anyhow::{Context as _, Result, format_err};
+use serde_json::Value;
+
+use crate::{
+    cargo::Workspace,
+    lockfile::{self, Lockfile},
+};
+
+/// The dependency graph resolved with dev-dependencies, read from full
+/// `cargo metadata`.
+pub(crate) struct Graph {
+    packages: Vec<Package>,
+    members: Vec<usize>,
+}
+
+struct Package {
+    name: String,
+    version: semver::Version,
+    /// Version requirements in `dependencies` of `cargo metadata`.
+    reqs: Vec<Req>,
+    /// Resolved dependencies in `resolve.nodes[].deps` of `cargo metadata`.
+    deps: Vec<Dep>,
+}
+
+struct Req {
+    name: String,
+    req: String,
+    dev: bool,
+}
+
+struct Dep {
+    index: usize,
+    /// Whether this is only a dev-dependency.
+    dev_only: bool,
+}
+
+struct Raised {
+    name: String,
+    /// The version resolved without dev-dependencies.
+    normal: semver::Version,
+    /// The version resolved with dev-dependencies.
+    raised: semver::Version,
+    /// Descriptions of the requirements that raise the version.
+    causes: BTreeSet<String>,
+}
+
+impl Graph {
+    /// Reads the dependency graph of the current Cargo.lock.
+    pub(crate) fn new(ws: &Workspace) -> Result<Self> {
+        let mut cmd = ws.cargo();
+        cmd.args(["metadata", "--format-version=1", "--manifest-path"]);
+        cmd.arg(ws.metadata.workspace_root.join("Cargo.toml"));
+        let json = cmd.read()?;
+        let value: Value = serde_json::from_str(&json)
+            .with_context(|| format!("failed to parse output from {cmd}"))?;
+        Self::from_value(&value).ok_or_else(|| format_err!("failed to parse output from {cmd}"))
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        let raw_packages = value.get("packages")?.as_array()?;
+        let mut packages = Vec::with_capacity(raw_packages.len());
+        let mut index = HashMap::with_capacity(raw_packages.len());
+        for (i, package) in raw_packages.iter().enumerate() {
+            index.insert(package.get("id")?.as_str()?, i);
+            let reqs = package
+                .get("dependencies")?
+                .as_array()?
+                .iter()
+                .map(|dep| {
+                    Some(Req {
+                        name: dep.get("name")?.as_str()?.to_owned(),
+                        req: dep.get("req")?.as_str()?.to_owned(),
+                        dev: dep.get("kind").and_then(Value::as_str) == Some("dev"),
+                    })
+                })
+                .collect::<Option<_>>()?;
+            packages.push(Package {
+                name: package.get("name")?.as_str()?.to_owned(),
+                version: package.get("version")?.as_str()?.parse().ok()?,
+                reqs,
+                deps: vec![],
+            });
+        }
+        for node in value.get("resolve")?.get("nodes")?.as_array()? {
+            let i = *index.get(node.get("id")?.as_str()?)?;
+            for dep in node.get("deps")?.as_array()? {
+                // `dep_kinds` field was added in Rust 1.41.
+                let kinds = dep.get("dep_kinds").and_then(Value::as_array);
+                let dev_only = kinds.is_some_and(|kinds| {
+                    !kinds.is_empty()
+                        && kinds
+                            .iter()
+                            .all(|k| k.get("kind").and_then(Value::as_str) == Some("dev"))
+                });
+                let index = *index.get(dep.get("pkg")?.as_str()?)?;
+                packages[i].deps.push(Dep { index, dev_only });
+            }
+        }
+        let members = value
+            .get("workspace_members")?
+            .as_array()?
+            .iter()
+            .map(|id| index.get(id.as_str()?).copied())
+            .collect::<Option<_>>()?;
+        Some(Self { packages, members })
+    }
+
+    /// Returns the packages reachable from `start` without dev-dependencies,
+    /// including `start` itself.
+    fn reachable(&self, start: usize) -> Vec<usize> {
+        let mut visited = HashSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            for dep in &self.packages[i].deps {
+                if !dep.dev_only && visited.insert(dep.index) {
+                    stack.push(dep.index);
+                }
+            }
+        }
+        let mut reachable: Vec<_> = visited.into_iter().collect();
+        reachable.sort_unstable();
+        reachable
+    }
+
+    /// Returns descriptions of the requirements reachable from dev-dependencies
+    /// that exclude the `normal` version of the `raised` package.
+    fn causes(&self, normal: &semver::Version, raised: usize) -> BTreeSet<String> {
+        let package = &self.packages[raised];
+        let name = &package.name;
+        let raises = |req: &Req| {
+            req.name == *name
+                && semver::VersionReq::parse(&req.req)
+                    .is_ok_and(|r| !r.matches(normal) && r.matches(&package.version))
+        };
+        let mut causes = BTreeSet::new();
+        for &m in &self.members {
+            let member = &self.packages[m];
+            for req in member.reqs.iter().filter(|r| r.dev && raises(r)) {
+                causes.insert(format!(
+                    "required by `{name} = \"{}\"` in dev-dependencies of {}",
+                    req.req, member.name
+                ));
+            }
+            for dev_dep in member.deps.iter().filter(|d| d.dev_only) {
+                let dev_dep_name = &self.packages[dev_dep.index].name;
+                for q in self.reachable(dev_dep.index) {
+                    let q_package = &self.packages[q];
+                    if !q_package.deps.iter().any(|d| d.index == raised && !d.dev_only) {
+                        continue;
+                    }
+                    for req in q_package.reqs.iter().filter(|r| !r.dev && raises(r)) {
+                        causes.insert(if q == dev_dep.index {
+                            format!(
+                                "required by `{name} = \"{}\"` in dev-dependency {dev_dep_name} {} of {}",
+                                req.req, q_package.version, member.name
+                            )
+                        } else {
+                            format!(
+                                "required by `{name} = \"{}\"` in {} {}, via dev-dependency {dev_dep_name} of {}",
+                                req.req, q_package.name, q_package.version, member.name
+                            )
+                        });
+                    }
+                }
+            }
+        }
+        causes
+    }
+}
+
+/// Returns packages in `normal` (resolved without dev-dependencies) whose
+/// versions are raised in `graph` (resolved with dev-dependencies).
+fn raised(normal: &Lockfile, graph: &Graph) -> Vec<Raised> {
+    let mut seen = HashSet::new();
+    let mut raised = vec![];
+    for package in &normal.packages {
+        let version = &package.version;
+        if !seen.insert((&package.name, version)) {
+            continue;
+        }
+        let mut versions =
+            graph.packages.iter().enumerate().filter(|(_, p)| p.name == package.name);
+        if versions.clone().any(|(_, p)| p.version == *version) {
+            continue;
+        }
+        let Some((i, p)) = versions.find(|(_, p)| {
+            p.version > *version && lockfile::is_semver_compatible(&p.version, version)
+        }) else {
+            continue;
+        };
+        raised.push(Raised {
+            name: package.name.clone(),
+            normal: version.clone(),
+            raised: p.version.clone(),
+            causes: graph.causes(version, i),
+        });
+    }
+    raised
+}
+
+/// Prints normal dependencies whose minimal versions are raised by
+/// dev-dependencies, and the requirements that raise them.
+pub(crate) fn print(normal: &Lockfile, graph: &Graph) {
+    let raised = raised(normal, graph);
+    if raised.is_empty() {
+        info!("no normal dependencies are raised by dev-dependencies");
+        return;
+    }
+    for r in &raised {
+        println!("{} {} -> {}", r.name, r.normal, r.raised);
+        if r.causes.is_empty() {
+            // e.g., raised by features enabled by dev-dependencies.
+            println!("    not required by dev-dependencies directly");
+        }
+        for cause in &r.causes {
+            println!("    {cause}");
+        }
+    }
+    warn!(
+        "{} normal dependenc{} raised by dev-dependencies; tests run against newer versions \
+         than the minimal versions of normal dependencies",
+        raised.len(),
+        if raised.len() == 1 { "y is" } else { "ies are" }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::{Graph, raised};
+    use crate::lockfile::Lockfile;
+
+    #[test]
+    fn causes() {
+        let metadata = json!({
+            "packages": [
+                {
+                    "id": "m", "name": "m", "version": "0.1.0",
+                    "dependencies": [
+                        { "name": "a", "req": "^1.0.1", "kind": null },
+                        { "name": "a", "req": "^1.0.2", "kind": "dev" },
+                        { "name": "t", "req": "^0.1", "kind": "dev" },
+                    ],
+                },
+                { "id": "a", "name": "a", "version": "1.0.2", "dependencies": [] },
+                { "id": "b", "name": "b", "version": "0.3.5", "dependencies": [] },
+                {
+                    "id": "t", "name": "t", "version": "0.1.0",
+                    "dependencies": [{ "name": "u", "req": "^2", "kind": null }],
+                },
+                {
+                    "id": "u", "name": "u", "version": "2.0.0",
+                    "dependencies": [
+                        { "name": "b", "req": "^0.3.5", "kind": null },
+                        { "name": "a", "req": "^1.0.5", "kind": "dev" },
+                    ],
+                },
+            ],
+            "workspace_members": ["m"],
+            "resolve": { "nodes": [
+                { "id": "m", "deps": [
+                    { "pkg": "a", "dep_kinds": [{ "kind": null }, { "kind": "dev" }] },
+                    { "pkg": "t", "dep_kinds": [{ "kind": "dev" }] },
+                ] },
+                { "id": "a", "deps": [] },
+                { "id": "b", "deps": [] },
+                { "id": "t", "deps": [{ "pkg": "u", "dep_kinds": [{ "kind": null }] }] },
+                { "id": "u", "deps": [{ "pkg": "b", "dep_kinds": [{ "kind": null }] }] },
+            ] },
+        });
+        let graph = Graph::from_value(&metadata).unwrap();
+        let normal = Lockfile::from_str(
+            "[[package]]\nname = \"m\"\nversion = \"0.1.0\"\n\
+             [[package]]\nname = \"a\"\nversion = \"1.0.1\"\n\
+             [[package]]\nname = \"b\"\nversion = \"0.3.0\"\n",
+            Path::new("Cargo.lock"),
+        )
+        .unwrap();
+        let raised = raised(&normal, &graph);
+        let raised: Vec<_> = raised
+            .iter()
+            .map(|r| {
+                (
+                    &*r.name,
+                    r.normal.to_string(),
+                    r.raised.to_string(),
+                    r.causes.iter().map(String::as_str).collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(raised, [
+            ("a", "1.0.1".to_owned(), "1.0.2".to_owned(), vec![
+                "required by `a = \"^1.0.2\"` in dev-dependencies of m"
+            ]),
+            ("b", "0.3.0".to_owned(), "0.3.5".to_owned(), vec![
+                "required by `b = \"^0.3.5\"` in u 2.0.0, via dev-dependency t of m"
+            ]),
+        ]);
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// A minimal line-based unified diff, used to preview changes to manifests.
+
+use std::fmt::Write as _;
+
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Returns a unified diff between `old` and `new`, or an empty string if they
+/// are the same.
+pub(crate) fn unified(path: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff(&old, &new);
+
+    let mut out = String::new();
+    let changes: Vec<usize> =
+        ops.iter().enumerate().filter(|(_, (op, ..))| *op != Op::Equal).map(|(i, _)| i).collect();
+    if changes.is_empty() {
+        return out;
+    }
+    let _ = writeln!(out, "--- a/{path}");
+    let _ = writeln!(out, "+++ b/{path}");
+
+    let mut i = 0;
+    while i < changes.len() {
+        // Merge changes that are close enough to share their context lines.
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= CONTEXT * 2 {
+            j += 1;
+        }
+        let start = changes[i].saturating_sub(CONTEXT);
+        let end = (changes[j] + CONTEXT + 1).min(ops.len());
+        let hunk = &ops[start..end];
+
+        let (_, old_start, new_start) = ops[start];
+        let old_len = hunk.iter().filter(|(op, ..)| *op != Op::Insert).count();
+        let new_len = hunk.iter().filter(|(op, ..)| *op != Op::Delete).count();
+        let _ =
+            writeln!(out, "@@ -{} +{} @@", range(old_start, old_len), range(new_start, new_len));
+        for &(op, o, n) in hunk {
+            match op {
+                Op::Equal => {
+                    let _ = writeln!(out, " {}", old[o]);
+                }
+                Op::Delete => {
+                    let _ = writeln!(out, "-{}", old[o]);
+                }
+                Op::Insert => {
+                    let _ = writeln!(out, "+{}", new[n]);
+                }
+            }
+        }
+        i = j + 1;
+    }
+    out
+}
+
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// Returns the list of operations with the indices of old and new lines at
+/// that point.
+fn diff(old: &[&str], new: &[&str]) -> Vec<(Op, usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // Longest common subsequence of the changed region.
+    let width = new_mid.len() + 1;
+    let mut lcs = vec![0_u32; (old_mid.len() + 1) * width];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    for k in 0..prefix {
+        ops.push((Op::Equal, k, k));
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            ops.push((Op::Equal, prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if i < old_mid.len()
+            && (j == new_mid.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            ops.push((Op::Delete, prefix + i, prefix + j));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, prefix + i, prefix + j));
+            j += 1;
+        }
+    }
+    for k in 0..suffix {
+        ops.push((Op::Equal, prefix + old_mid.len() + k, prefix + new_mid.len() + k));
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified;
+
+    #[test]
+    fn same() {
+        assert_eq!(unified("Cargo.toml", "a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn change() {
+        let old = "[package]\nname = \"a\"\n\n[dependencies]\nb = \"1\"\nc = \"1\"\n";
+        let new = "[package]\nname = \"a\"\n\n[dependencies]\nb = \"1.2.3\"\nc = \"1\"\n";
+        assert_eq!(
+            unified("Cargo.toml", old, new),
+            "\
+--- a/Cargo.toml
++++ b/Cargo.toml
+@@ -2,5 +2,5 @@
+ name = \"a\"
+ \n [dependencies]
+-b = \"1\"
++b = \"1.2.3\"
+ c = \"1\"
+"
+        );
+    }
+
+    #[test]
+    fn remove_and_add() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified("f", old, new),
+            "\
+--- a/f
++++ b/f
+@@ -1,4 +1,3 @@
+-1
+ 2
+ 3
+ 4
+@@ -10,3 +9,4 @@
+ 10
+ 11
+ 12
++13
+"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of `cargo minimal-versions fix`.
+//
+// Raises too-low version requirements of direct dependencies to the versions
+// recorded in the existing Cargo.lock.
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{Context as _, Result, bail};
+
+use crate::{cli::Args, diff, fs, lockfile::Lockfile, metadata::Metadata};
+
+pub(crate) fn run(metadata: &Metadata, args: &Args) -> Result<()> {
+    let workspace_root = &metadata.workspace_root;
+    let lockfile_path = &workspace_root.join("Cargo.lock");
+    if !lockfile_path.exists() {
+        bail!(
+            "Cargo.lock not found in {}; `cargo minimal-versions fix` raises version requirements \
+             to the versions recorded in Cargo.lock",
+            workspace_root.display()
+        );
+    }
+    let lockfile = Lockfile::new(lockfile_path)?;
+
+    let root_manifest = workspace_root.join("Cargo.toml");
+    let mut manifests: Vec<&Path> =
+        metadata.workspace_members.iter().map(|&id| &*metadata[id].manifest_path).collect();
+    // [workspace.dependencies] in virtual manifest.
+    if !manifests.iter().any(|&p| p == root_manifest) {
+        manifests.push(&root_manifest);
+    }
+
+    let mut found = HashSet::new();
+    let mut changed = false;
+    for manifest_path in manifests {
+        let orig = fs::read_to_string(manifest_path)?;
+        let mut doc: toml_edit::DocumentMut = orig.parse().with_context(|| {
+            format!("failed to parse manifest `{}` as toml", manifest_path.display())
+        })?;
+        let changes = raise_reqs(&mut doc, &lockfile, &args.deps, &mut found);
+        if changes.is_empty() {
+            continue;
+        }
+        changed = true;
+        let new = doc.to_string();
+        let display_path = manifest_path.strip_prefix(workspace_root).unwrap_or(manifest_path);
+        // --dry-run is the same as --diff for fix subcommand.
+        if args.diff || args.dry_run {
+            print!("{}", diff::unified(&display_path.to_string_lossy(), &orig, &new));
+        } else {
+            for change in &changes {
+                info!(
+                    "raising version requirement of `{}` from `{}` to `{}` in {}",
+                    change.name,
+                    change.from,
+                    change.to,
+                    display_path.display()
+                );
+            }
+            fs::write_atomic(manifest_path, new.as_bytes(), None)?;
+        }
+    }
+    for name in &args.deps {
+        if !found.contains(name) {
+            warn!("dependency `{name}` not found in any manifest in the workspace");
+        }
+    }
+    if !changed {
+        info!("no version requirements to raise");
+    }
+    Ok(())
+}
+
+struct Change {
+    name: String,
+    from: String,
+    to: String,
+}
+
+/// Raises version requirements of dependencies in [dependencies],
+/// [build-dependencies], [target.'...'.dependencies],
+/// [target.'...'.build-dependencies], and [workspace.dependencies].
+///
+/// If `filter` is not empty, only dependencies with the given names are
+/// changed (`--all` is passed otherwise).
+fn raise_reqs(
+    doc: &mut toml_edit::DocumentMut,
+    lockfile: &Lockfile,
+    filter: &[String],
+    found: &mut HashSet<String>,
+) -> Vec<Change> {
+    const KIND: &[&str] = &["build-dependencies", "dependencies"];
+    let mut changes = vec![];
+    let mut raise = |deps: &mut toml_edit::Item| {
+        let Some(deps) = deps.as_table_like_mut() else { return };
+        for (key, dep) in deps.iter_mut() {
+            let key = key.get();
+            // Handle renamed dependencies.
+            let name = dep
+                .as_table_like()
+                .and_then(|t| t.get("package"))
+                .and_then(toml_edit::Item::as_str)
+                .unwrap_or(key)
+                .to_owned();
+            if !filter.is_empty() {
+                let mut matched = false;
+                for f in filter {
+                    if f == key || *f == name {
+                        found.insert(f.clone());
+                        matched = true;
+                    }
+                }
+                if !matched {
+                    continue;
+                }
+            }
+            let req = match dep {
+                toml_edit::Item::Value(toml_edit::Value::String(_)) => dep.as_value_mut(),
+                _ => dep.as_table_like_mut().and_then(|t| t.get_mut("version")?.as_value_mut()),
+            };
+            let Some(req) = req else { continue };
+            let Some(from) = req.as_str().map(str::to_owned) else { continue };
+            let Some(to) = raise_req(&from, &lockfile.versions(&name).collect::<Vec<_>>()) else {
+                continue;
+            };
+            let decor = req.decor().clone();
+            *req = to.as_str().into();
+            *req.decor_mut() = decor;
+            changes.push(Change { name, from, to });
+        }
+    };
+    for key in KIND {
+        if let Some(deps) = doc.get_mut(key) {
+            raise(deps);
+        }
+    }
+    if let Some(table) = doc.get_mut("target").and_then(toml_edit::Item::as_table_like_mut) {
+        for (_key, val) in table.iter_mut() {
+            if let Some(table) = val.as_table_like_mut() {
+                for key in KIND {
+                    if let Some(deps) = table.get_mut(key) {
+                        raise(deps);
+                    }
+                }
+            }
+        }
+    }
+    // [workspace.dependencies]
+    if let Some(table) = doc.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut) {
+        if let Some(deps) = table.get_mut("dependencies") {
+            raise(deps);
+        }
+    }
+    changes
+}
+
+/// Returns the new version requirement if the lower bound of `req` is lower
+/// than the highest locked version that matches `req`.
+fn raise_req(req: &str, locked: &[&semver::Version]) -> Option<String> {
+    let parsed = semver::VersionReq::parse(req).ok()?;
+    // TODO: support requirements with multiple comparators (e.g., ">=1.2, <2").
+    if parsed.comparators.len() != 1 {
+        return None;
+    }
+    let comparator = parsed.comparators.first().unwrap();
+    if !matches!(comparator.op, semver::Op::Caret | semver::Op::Tilde | semver::Op::GreaterEq)
+        || !comparator.pre.is_empty()
+    {
+        // Exact requirements are intentional, and others have no lower bound
+        // that can be raised.
+        return None;
+    }
+    let lower = semver::Version::new(
+        comparator.major,
+        comparator.minor.unwrap_or(0),
+        comparator.patch.unwrap_or(0),
+    );
+    let locked = locked.iter().filter(|v| v.pre.is_empty() && parsed.matches(v)).max()?;
+    if **locked <= lower {
+        return None;
+    }
+    // Preserve the operator as written (e.g., "1" vs "^1").
+    let req = req.trim();
+    let op = &req[..req.find(|c: char| c.is_ascii_digit())?];
+    Some(format!("{op}{}.{}.{}", locked.major, locked.minor, locked.patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::Path};
+
+    use crate::lockfile::Lockfile;
+
+    macro_rules! test {
+        ($name:ident, $filter:expr, $input:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                let lockfile = Lockfile::from_str(LOCKFILE, Path::new("Cargo.lock")).unwrap();
+                let mut doc: toml_edit::DocumentMut = $input.parse().unwrap();
+                let filter: Vec<String> = $filter.iter().map(|s: &&str| (*s).to_owned()).collect();
+                super::raise_reqs(&mut doc, &lockfile, &filter, &mut HashSet::new());
+                assert_eq!($expected, doc.to_string());
+            }
+        };
+    }
+
+    const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "a"
+version = "1.2.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "b"
+version = "0.1.5"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "b"
+version = "0.2.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "c"
+version = "2.0.0-rc.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+
+    test!(
+        deps,
+        [] as [&str; 0],
+        "\
+[dependencies]
+a = \"1\" # comment
+b = { version = \"0.1\", features = [] }
+b2 = { package = \"b\", version = \"0.2.0\" }
+c = \"2.0.0-rc.1\"
+[build-dependencies.a]
+version = \"^1.2.3\"
+[target.'cfg(unix)'.dependencies]
+a = \"~1.1\"
+b = \">=0.1, <0.3\"
+[dev-dependencies]
+a = \"1\"
+",
+        "\
+[dependencies]
+a = \"1.2.3\" # comment
+b = { version = \"0.1.5\", features = [] }
+b2 = { package = \"b\", version = \"0.2.1\" }
+c = \"2.0.0-rc.1\"
+[build-dependencies.a]
+version = \"^1.2.3\"
+[target.'cfg(unix)'.dependencies]
+a = \"~1.1\"
+b = \">=0.1, <0.3\"
+[dev-dependencies]
+a = \"1\"
+"
+    );
+
+    test!(
+        workspace_deps,
+        ["b"],
+        "\
+[workspace.dependencies]
+a = \"1\"
+b = \">=0.2\"
+x = { version = \"1\", path = \"x\" }
+",
+        "\
+[workspace.dependencies]
+a = \"1\"
+b = \">=0.2.1\"
+x = { version = \"1\", path = \"x\" }
+"
+    );
+}
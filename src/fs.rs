@@ -1,8 +1,17 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use std::path::Path;
+use std::{
+    env,
+    hash::{BuildHasher as _, Hasher as _, RandomState},
+    io::{self, Write as _},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
 
-use anyhow::{Context as _, Result};
+use anyhow::{Context as _, Result, bail};
+use fs4::TryLockError;
 
 /// A wrapper for [`std::fs::write`].
 pub(crate) fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
@@ -11,6 +20,93 @@ pub(crate) fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Resul
     res.with_context(|| format!("failed to write to file `{}`", path.display()))
 }
 
+/// Writes `contents` to a temporary file next to `path` and renames it to
+/// `path`, so that `path` is never left partially written.
+///
+/// If `path` is a symlink, the file it points to is written instead of
+/// replacing the symlink. The permissions and owner of the existing file are
+/// kept; if the owner cannot be kept, or the file has other hard links, the
+/// file is written in place instead. If `mtime` is set, it is used as the
+/// modification time of the new file.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8], mtime: Option<SystemTime>) -> Result<()> {
+    let real = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let metadata = std::fs::metadata(real).ok();
+    let res = match replace(real, contents, mtime, metadata.as_ref()) {
+        Ok(true) => Ok(()),
+        Ok(false) => write_in_place(real, contents, mtime),
+        Err(e) => Err(e),
+    };
+    res.with_context(|| format!("failed to write to file `{}`", path.display()))
+}
+
+/// Replaces `path` with a new file, and returns whether it was replaced.
+///
+/// Returns `false` if the file at `path` cannot be replaced without breaking
+/// its hard links or changing its owner.
+fn replace(
+    path: &Path,
+    contents: &[u8],
+    mtime: Option<SystemTime>,
+    metadata: Option<&std::fs::Metadata>,
+) -> io::Result<bool> {
+    #[cfg(unix)]
+    if metadata.is_some_and(|m| std::os::unix::fs::MetadataExt::nlink(m) > 1) {
+        return Ok(false);
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let res = (|| {
+        let mut file = std::fs::File::create(&tmp)?;
+        if let Some(metadata) = metadata {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt as _;
+                let new = file.metadata()?;
+                if (new.uid(), new.gid()) != (metadata.uid(), metadata.gid())
+                    && std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()))
+                        .is_err()
+                {
+                    return Ok(false);
+                }
+            }
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents)?;
+        if let Some(mtime) = mtime {
+            file.set_modified(mtime)?;
+        }
+        drop(file);
+        std::fs::rename(&tmp, path)?;
+        Ok(true)
+    })();
+    if !matches!(res, Ok(true)) {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Writes `contents` to the existing file at `path` in place.
+fn write_in_place(path: &Path, contents: &[u8], mtime: Option<SystemTime>) -> io::Result<()> {
+    let mut file = std::fs::File::options().write(true).truncate(true).open(path)?;
+    file.write_all(contents)?;
+    if let Some(mtime) = mtime {
+        file.set_modified(mtime)?;
+    }
+    Ok(())
+}
+
+/// Returns the modification time of the given path, if available.
+pub(crate) fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Sets the modification time of the given path.
+pub(crate) fn set_modified(path: &Path, mtime: SystemTime) -> Result<()> {
+    let res = std::fs::File::options().write(true).open(path).and_then(|f| f.set_modified(mtime));
+    res.with_context(|| format!("failed to set modification time of file `{}`", path.display()))
+}
+
 /// A wrapper for [`std::fs::read`].
 pub(crate) fn read(path: impl AsRef<Path>) -> Result<Vec<u8>> {
     let path = path.as_ref();
@@ -24,3 +120,202 @@ pub(crate) fn read_to_string(path: impl AsRef<Path>) -> Result<String> {
     let res = std::fs::read_to_string(path);
     res.with_context(|| format!("failed to read from file `{}`", path.display()))
 }
+
+/// A wrapper for [`std::fs::create_dir_all`].
+pub(crate) fn create_dir_all(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let res = std::fs::create_dir_all(path);
+    res.with_context(|| format!("failed to create directory `{}`", path.display()))
+}
+
+/// A wrapper for [`std::fs::remove_dir_all`].
+pub(crate) fn remove_dir_all(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let res = std::fs::remove_dir_all(path);
+    res.with_context(|| format!("failed to remove directory `{}`", path.display()))
+}
+
+/// Removes the file at the given path, if it exists.
+pub(crate) fn remove_file_if_exists(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove file `{}`", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The temporary directories alive in the process, so that the signal handler
+// can remove them.
+static TEMP_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Removes all temporary directories alive in the process.
+///
+/// This is called by the signal handler, which exits the process without
+/// dropping them.
+pub(crate) fn remove_temp_dirs() {
+    for path in mem::take(&mut *TEMP_DIRS.lock().unwrap_or_else(PoisonError::into_inner)) {
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
+
+/// A temporary directory that is removed when dropped, or by the signal
+/// handler.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates a new directory with a unique name in the system temporary
+    /// directory.
+    ///
+    /// The directory is created exclusively, so an existing directory (e.g.,
+    /// one created by another user to predict the name) is never reused or
+    /// removed.
+    pub(crate) fn new(name: &str) -> Result<Self> {
+        const ATTEMPTS: u32 = 16;
+        let tmp = env::temp_dir();
+        for _ in 0..ATTEMPTS {
+            // RandomState is randomly seeded per instance.
+            let random = RandomState::new().build_hasher().finish();
+            let path = tmp.join(format!("{name}-{}-{random:016x}", std::process::id()));
+            let mut builder = std::fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            match builder.create(&path) {
+                Ok(()) => {
+                    TEMP_DIRS.lock().unwrap_or_else(PoisonError::into_inner).push(path.clone());
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to create directory `{}`", path.display())
+                    });
+                }
+            }
+        }
+        bail!(
+            "failed to create a unique temporary directory in `{}` after {ATTEMPTS} attempts",
+            tmp.display()
+        )
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Creates a temporary directory for a test, which is removed when the test
+/// finishes, even if it panics.
+#[cfg(test)]
+pub(crate) fn test_dir() -> TempDir {
+    TempDir::new("cargo-minimal-versions-test").unwrap()
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        TEMP_DIRS.lock().unwrap_or_else(PoisonError::into_inner).retain(|p| *p != self.path);
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// An advisory lock on a file, which is released when dropped.
+pub(crate) struct FileLock {
+    _file: std::fs::File,
+}
+
+impl FileLock {
+    /// Takes an exclusive lock on the given path, creating the file if it
+    /// doesn't exist.
+    ///
+    /// If the lock is held by another process, waits for it to be released
+    /// unless `no_wait` is set, in which case an error is returned.
+    pub(crate) fn acquire(path: &Path, no_wait: bool) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("failed to open lock file `{}`", path.display()))?;
+        // Use fully qualified syntax to avoid calling `std::fs::File::try_lock`
+        // on newer Rust, which has a different signature.
+        match fs4::FileExt::try_lock(&file) {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                if no_wait {
+                    bail!(
+                        "another cargo-minimal-versions is running in this workspace \
+                         (lock file: {})",
+                        path.display()
+                    );
+                }
+                info!(
+                    "waiting for another cargo-minimal-versions to finish (lock file: {})",
+                    path.display()
+                );
+                fs4::FileExt::lock(&file)
+                    .with_context(|| format!("failed to lock file `{}`", path.display()))?;
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("failed to lock file `{}`", path.display()));
+            }
+        }
+        Ok(Self { _file: file })
+    }
+
+    /// Returns whether the given path is locked by another process.
+    pub(crate) fn is_locked(path: &Path) -> bool {
+        let Ok(file) = std::fs::File::open(path) else { return false };
+        matches!(fs4::FileExt::try_lock(&file), Err(TryLockError::WouldBlock))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileLock, test_dir, write_atomic};
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_symlink() {
+        let tmp = test_dir();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("ws")).unwrap();
+        let target = &dir.join("Cargo.toml");
+        let link = &dir.join("ws/Cargo.toml");
+        std::fs::write(target, "a").unwrap();
+        std::os::unix::fs::symlink("../Cargo.toml", link).unwrap();
+
+        write_atomic(link, b"b", None).unwrap();
+        assert!(std::fs::symlink_metadata(link).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(target).unwrap(), b"b");
+
+        // Hard links are kept by writing in place.
+        let hard_link = &dir.join("hard_link");
+        std::fs::hard_link(target, hard_link).unwrap();
+        write_atomic(link, b"c", None).unwrap();
+        assert!(std::fs::symlink_metadata(link).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(target).unwrap(), b"c");
+        assert_eq!(std::fs::read(hard_link).unwrap(), b"c");
+    }
+
+    #[test]
+    fn file_lock() {
+        let tmp = test_dir();
+        let path = &tmp.path().join("target/minimal-versions.lock");
+        assert!(!FileLock::is_locked(path));
+
+        let lock = FileLock::acquire(path, true).unwrap();
+        assert!(FileLock::is_locked(path));
+        let e = FileLock::acquire(path, true).err().unwrap();
+        assert!(e.to_string().contains("another cargo-minimal-versions is running"), "{e}");
+        drop(lock);
+        assert!(!FileLock::is_locked(path));
+        let _lock = FileLock::acquire(path, true).unwrap();
+    }
+}
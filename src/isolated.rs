@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --isolated.
+//
+// Copies the workspace into a temporary directory and runs everything there,
+// so that the user's checkout is never modified.
+
+use std::{env, path::Path};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    cargo::Workspace,
+    cli::Args,
+    fs::{self, TempDir},
+    term,
+};
+
+/// Copies the workspace into a temporary directory, and switches `ws`, `args`,
+/// and the current directory to the copy.
+///
+/// The returned directory is removed when dropped. With --dry-run, nothing is
+/// copied and `None` is returned, since the workspace is not modified anyway.
+pub(crate) fn enter(ws: &mut Workspace, args: &mut Args) -> Result<Option<TempDir>> {
+    if args.dry_run {
+        info!("workspace would be copied to a temporary directory");
+        return Ok(None);
+    }
+    let orig_root = ws.metadata.workspace_root.clone();
+    let target_dir = ws.metadata.target_directory.clone();
+    let tmp = TempDir::new("cargo-minimal-versions")?;
+    let root = tmp.path().join(orig_root.file_name().unwrap_or_else(|| "workspace".as_ref()));
+    info!("copying workspace to {}", root.display());
+    copy_dir(&orig_root, &root, &[&target_dir, &orig_root.join(".git")])?;
+
+    // Path dependencies outside of the workspace are not copied, so refer to
+    // them by absolute paths.
+    let mut manifests: Vec<&Path> =
+        ws.metadata.workspace_members.iter().map(|&id| &*ws.metadata[id].manifest_path).collect();
+    let root_manifest = orig_root.join("Cargo.toml");
+    if !manifests.contains(&&*root_manifest) {
+        manifests.push(&root_manifest);
+    }
+    for orig_manifest in manifests {
+        let Ok(rel) = orig_manifest.strip_prefix(&orig_root) else { continue };
+        let manifest_path = root.join(rel);
+        let raw = fs::read_to_string(&manifest_path)?;
+        let mut doc: toml_edit::DocumentMut = raw.parse().with_context(|| {
+            format!("failed to parse manifest `{}` as toml", orig_manifest.display())
+        })?;
+        if absolutize_external_paths(&mut doc, orig_manifest.parent().unwrap(), &orig_root) {
+            fs::write(&manifest_path, doc.to_string())?;
+        }
+    }
+
+    let cwd = env::current_dir()?;
+    if let Some(lockfile_out) = &mut args.lockfile_out {
+        *lockfile_out = cwd.join(&*lockfile_out).to_string_lossy().into_owned();
+    }
+    if let Some(manifest_path) = &mut args.manifest_path {
+        let path = cwd.join(&*manifest_path);
+        if let Ok(rel) = path.strip_prefix(&orig_root) {
+            *manifest_path = root.join(rel).to_string_lossy().into_owned();
+        }
+    }
+    if let Ok(rel) = cwd.strip_prefix(&orig_root) {
+        env::set_current_dir(root.join(rel))?;
+    }
+    // Use a separate target directory so that the user's builds are not
+    // blocked by the build directory lock, and the build cache is reused
+    // between runs.
+    let target_dir = target_dir.join("minimal-versions");
+    if term::verbose() {
+        info!("using {} as target directory", target_dir.display());
+    }
+    ws.target_dir = Some(target_dir);
+
+    ws.reload_metadata(args.manifest_path.as_deref())?;
+    Ok(Some(tmp))
+}
+
+fn copy_dir(from: &Path, to: &Path, exclude: &[&Path]) -> Result<()> {
+    fs::create_dir_all(to)?;
+    let entries =
+        std::fs::read_dir(from).with_context(|| format!("failed to read `{}`", from.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let from = entry.path();
+        if exclude.contains(&&*from) {
+            continue;
+        }
+        let to = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            #[cfg(unix)]
+            {
+                let link = std::fs::read_link(&from)?;
+                std::os::unix::fs::symlink(link, &to)
+                    .with_context(|| format!("failed to create symlink `{}`", to.display()))?;
+                continue;
+            }
+        }
+        if from.is_dir() {
+            copy_dir(&from, &to, exclude)?;
+        } else {
+            std::fs::copy(&from, &to).with_context(|| {
+                format!("failed to copy `{}` to `{}`", from.display(), to.display())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `path` fields in dependency tables that point outside of the
+/// workspace with absolute paths, and returns whether the document was changed.
+fn absolutize_external_paths(
+    doc: &mut toml_edit::DocumentMut,
+    manifest_dir: &Path,
+    workspace_root: &Path,
+) -> bool {
+    const KIND: &[&str] = &["build-dependencies", "dependencies", "dev-dependencies"];
+    let workspace_root = workspace_root.canonicalize().unwrap_or_else(|_| workspace_root.into());
+    let mut changed = false;
+    let mut absolutize = |deps: &mut toml_edit::Item| {
+        let Some(deps) = deps.as_table_like_mut() else { return };
+        for (_name, dep) in deps.iter_mut() {
+            let Some(path) =
+                dep.as_table_like_mut().and_then(|t| t.get_mut("path")?.as_value_mut())
+            else {
+                continue;
+            };
+            let Some(abs) = path
+                .as_str()
+                .and_then(|p| manifest_dir.join(p).canonicalize().ok())
+                .filter(|p| !p.starts_with(&workspace_root))
+            else {
+                continue;
+            };
+            let decor = path.decor().clone();
+            *path = abs.to_string_lossy().into_owned().into();
+            *path.decor_mut() = decor;
+            changed = true;
+        }
+    };
+    for key in KIND {
+        if let Some(deps) = doc.get_mut(key) {
+            absolutize(deps);
+        }
+    }
+    if let Some(table) = doc.get_mut("target").and_then(toml_edit::Item::as_table_like_mut) {
+        for (_key, val) in table.iter_mut() {
+            if let Some(table) = val.as_table_like_mut() {
+                for key in KIND {
+                    if let Some(deps) = table.get_mut(key) {
+                        absolutize(deps);
+                    }
+                }
+            }
+        }
+    }
+    // [workspace.dependencies]
+    if let Some(table) = doc.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut) {
+        if let Some(deps) = table.get_mut("dependencies") {
+            absolutize(deps);
+        }
+    }
+    // [patch.'...']
+    if let Some(table) = doc.get_mut("patch").and_then(toml_edit::Item::as_table_like_mut) {
+        for (_key, deps) in table.iter_mut() {
+            absolutize(deps);
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{absolutize_external_paths, copy_dir};
+    use crate::fs::{self, test_dir};
+
+    #[test]
+    fn copy_excludes() {
+        let tmp = test_dir();
+        let from = &tmp.path().join("ws");
+        for path in ["Cargo.toml", "src/lib.rs", "target/debug/foo", ".git/HEAD", "a/target/x"] {
+            let path = from.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let to = &tmp.path().join("copy");
+        copy_dir(from, to, &[&from.join("target"), &from.join(".git")]).unwrap();
+        assert!(to.join("Cargo.toml").is_file());
+        assert!(to.join("src/lib.rs").is_file());
+        assert!(!to.join("target").exists());
+        assert!(!to.join(".git").exists());
+        // Only the given paths are excluded.
+        assert!(to.join("a/target/x").is_file());
+    }
+
+    #[test]
+    fn absolutize() {
+        let tmp = test_dir();
+        let root = &tmp.path().canonicalize().unwrap();
+        for dir in ["ws/member", "ws/inner", "x", "y"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let ws = &root.join("ws");
+        let path =
+            |dir: &str| toml_edit::Value::from(&*root.join(dir).to_string_lossy()).to_string();
+        let (x, y) = (path("x"), path("y"));
+
+        let mut doc: toml_edit::DocumentMut = r#"
+[dependencies]
+x = { path = "../../x" } # external
+inner = { path = "../inner" }
+serde = "1"
+
+[target.'cfg(unix)'.dev-dependencies]
+y = { path = "../../y", version = "0.1" }
+"#
+        .parse()
+        .unwrap();
+        assert!(absolutize_external_paths(&mut doc, &ws.join("member"), ws));
+        assert_eq!(
+            doc.to_string(),
+            format!(
+                r#"
+[dependencies]
+x = {{ path = {x} }} # external
+inner = {{ path = "../inner" }}
+serde = "1"
+
+[target.'cfg(unix)'.dev-dependencies]
+y = {{ path = {y}, version = "0.1" }}
+"#
+            )
+        );
+
+        let mut doc: toml_edit::DocumentMut = r#"
+[workspace]
+members = ["member"]
+
+[workspace.dependencies]
+inner = { path = "inner" }
+
+[patch.crates-io]
+x = { path = "../x" }
+inner = { path = "inner" }
+
+[patch.'https://github.com/foo/bar']
+y = { path = "../y" }
+"#
+        .parse()
+        .unwrap();
+        assert!(absolutize_external_paths(&mut doc, ws, ws));
+        assert_eq!(
+            doc.to_string(),
+            format!(
+                r#"
+[workspace]
+members = ["member"]
+
+[workspace.dependencies]
+inner = {{ path = "inner" }}
+
+[patch.crates-io]
+x = {{ path = {x} }}
+inner = {{ path = "inner" }}
+
+[patch.'https://github.com/foo/bar']
+y = {{ path = {y} }}
+"#
+            )
+        );
+
+        // Paths inside the workspace and missing paths are kept as is.
+        let mut doc: toml_edit::DocumentMut =
+            "[dependencies]\ninner = { path = \"inner\" }\nz = { path = \"../z\" }\n"
+                .parse()
+                .unwrap();
+        assert!(!absolutize_external_paths(&mut doc, ws, ws));
+    }
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+/*!
+Library API of [cargo-minimal-versions], to drive minimal versions checks
+from `cargo xtask` or other tools.
+
+This provides the building blocks used by the `cargo minimal-versions`
+command:
+
+- [`manifest`]: transformations of `Cargo.toml` applied before resolving
+  minimal versions.
+- [`Workspace`]: the workspace metadata and the cargo to use for
+  `-Z minimal-versions`.
+- [`restore`]: a guard to restore modified files.
+
+# Examples
+
+```no_run
+use cargo_minimal_versions::{Workspace, manifest, restore};
+
+fn main() -> anyhow::Result<()> {
+    let ws = Workspace::load(None, false)?;
+    // Restore files on Ctrl-C. Skip this if the program installs its own handler,
+    // and call `restore::restore_all` from it instead.
+    restore::set_signal_handler()?;
+    let restore = restore::Manager::new();
+
+    let path = ws.workspace_root().join("Cargo.toml");
+    let orig = std::fs::read_to_string(&path)?;
+    let mut doc: toml_edit::DocumentMut = orig.parse()?;
+    manifest::remove_dev_deps(&mut doc);
+    restore.write(&path, orig, doc.to_string().as_bytes())?;
+
+    let lockfile = ws.workspace_root().join("Cargo.lock");
+    restore.register(&lockfile, std::fs::read(&lockfile)?)?;
+    let status = ws
+        .cargo_nightly_command()
+        .args(["update", "-Z", "minimal-versions"])
+        .status()?;
+    assert!(status.success());
+    let status = ws.cargo_command().args(["check", "--workspace"]).status()?;
+
+    // Restore the original Cargo.toml and Cargo.lock.
+    restore.restore_all();
+    assert!(status.success());
+    Ok(())
+}
+```
+
+[cargo-minimal-versions]: https://github.com/taiki-e/cargo-minimal-versions
+*/
+
+#![forbid(unsafe_code)]
+
+#[macro_use]
+mod term;
+
+#[macro_use]
+mod process;
+
+mod app;
+mod cargo;
+mod cli;
+mod config;
+mod dev_deps;
+mod diff;
+mod fix;
+mod fs;
+mod isolated;
+mod lockfile;
+pub mod manifest;
+mod metadata;
+mod no_modify;
+mod report;
+pub mod restore;
+mod runner;
+mod search;
+
+pub use crate::cargo::Workspace;
+
+/// Runs the `cargo minimal-versions` command. Not public API.
+#[doc(hidden)]
+#[must_use]
+pub fn __main() -> std::process::ExitCode {
+    app::main()
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use std::path::Path;
+
+use anyhow::{Context as _, Result, format_err};
+
+use crate::fs;
+
+type ParseResult<T> = Result<T, &'static str>;
+
+// Cargo.lock
+// https://doc.rust-lang.org/nightly/cargo/guide/cargo-toml-vs-cargo-lock.html
+pub(crate) struct Lockfile {
+    /// List of all packages recorded in the lockfile.
+    pub(crate) packages: Vec<Package>,
+}
+
+impl Lockfile {
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)?;
+        Self::from_str(&raw, path)
+    }
+
+    pub(crate) fn from_str(raw: &str, path: &Path) -> Result<Self> {
+        let doc: toml_edit::DocumentMut = raw
+            .parse()
+            .with_context(|| format!("failed to parse lockfile `{}` as toml", path.display()))?;
+        Self::from_table(&doc).map_err(|s| {
+            format_err!("failed to parse `{s}` field from lockfile `{}`", path.display())
+        })
+    }
+
+    fn from_table(doc: &toml_edit::DocumentMut) -> ParseResult<Self> {
+        let mut packages = vec![];
+        if let Some(array) = doc.get("package") {
+            let array = array.as_array_of_tables().ok_or("package")?;
+            packages.reserve(array.len());
+            for package in array {
+                packages.push(Package::from_table(package)?);
+            }
+        }
+        Ok(Self { packages })
+    }
+
+    /// Returns the versions of the package with the given name.
+    pub(crate) fn versions<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a semver::Version> + 'a {
+        self.packages.iter().filter(move |p| p.name == name).map(|p| &p.version)
+    }
+}
+
+pub(crate) struct Package {
+    pub(crate) name: String,
+    pub(crate) version: semver::Version,
+    /// The source ID of this package.
+    ///
+    /// This is `None` for path dependencies, including workspace members.
+    pub(crate) source: Option<String>,
+    /// The names of the dependencies of this package.
+    pub(crate) dependencies: Vec<String>,
+}
+
+impl Package {
+    fn from_table(table: &toml_edit::Table) -> ParseResult<Self> {
+        let name = table.get("name").and_then(toml_edit::Item::as_str).ok_or("name")?;
+        let version = table
+            .get("version")
+            .and_then(toml_edit::Item::as_str)
+            .and_then(|v| v.parse().ok())
+            .ok_or("version")?;
+        let source = table.get("source").map(|s| s.as_str().ok_or("source")).transpose()?;
+        let mut dependencies = vec![];
+        if let Some(array) = table.get("dependencies") {
+            // Each entry is "name", "name version", or "name version (source)".
+            for dep in array.as_array().ok_or("dependencies")? {
+                let dep = dep.as_str().ok_or("dependencies")?;
+                dependencies.push(dep.split(' ').next().unwrap().to_owned());
+            }
+        }
+        Ok(Self { name: name.to_owned(), version, source: source.map(str::to_owned), dependencies })
+    }
+
+    /// Returns whether this package is from crates.io.
+    pub(crate) fn is_crates_io(&self) -> bool {
+        matches!(
+            self.source.as_deref(),
+            Some(
+                "registry+https://github.com/rust-lang/crates.io-index"
+                    | "sparse+https://index.crates.io/"
+            )
+        )
+    }
+}
+
+/// Returns whether the two versions are semver-compatible.
+pub(crate) fn is_semver_compatible(a: &semver::Version, b: &semver::Version) -> bool {
+    if a.major != b.major {
+        false
+    } else if a.major != 0 {
+        true
+    } else if a.minor != b.minor {
+        false
+    } else {
+        a.minor != 0 || a.patch == b.patch
+    }
+}
@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
+//! Transformations of `Cargo.toml` applied before resolving minimal versions.
+
 // Adapted from https://github.com/taiki-e/cargo-no-dev-deps
 
 use std::{
@@ -9,12 +11,8 @@ use std::{
 
 use anyhow::{Context as _, Result, bail, format_err};
 
-use crate::{
-    cli::{Args, DetachPathDeps},
-    fs,
-    metadata::Metadata,
-    restore, term,
-};
+pub use crate::cli::DetachPathDeps;
+use crate::{cargo::Workspace, cli::Args, diff, fs, metadata::PackageId, process, restore, term};
 
 type ParseResult<T> = Result<T, &'static str>;
 
@@ -66,26 +64,80 @@ impl Package {
     }
 }
 
+/// Modifies manifests in the workspace, calls `f`, and then restores them.
+///
+/// If `only_member` is set, that member is made the only member of the
+/// workspace (--per-package-lockfile).
 pub(crate) fn with(
-    metadata: &Metadata,
+    ws: &Workspace,
     args: &Args,
     no_dev_deps: bool,
+    only_member: Option<PackageId>,
     f: impl FnOnce() -> Result<()>,
 ) -> Result<()> {
-    // TODO: provide option to keep updated Cargo.lock
-    let restore_lockfile = true;
+    modify(ws, args, no_dev_deps, only_member, false, f)
+}
+
+/// Same as [`with`], but `Cargo.lock` is always restored, regardless of
+/// --keep-lockfile, --lockfile-out, and failure.
+///
+/// This is used to resolve minimal versions only to inspect the result
+/// (--two-phase and dev-deps subcommand).
+pub(crate) fn resolve_only(
+    ws: &Workspace,
+    args: &Args,
+    no_dev_deps: bool,
+    only_member: Option<PackageId>,
+    f: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    modify(ws, args, no_dev_deps, only_member, true, f)
+}
+
+fn modify(
+    ws: &Workspace,
+    args: &Args,
+    no_dev_deps: bool,
+    only_member: Option<PackageId>,
+    intermediate: bool,
+    f: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let metadata = &ws.metadata;
+    let restore_lockfile = intermediate || !args.keep_lockfile;
     let no_private = args.no_private;
+    // Whether some members are removed from the workspace.
+    let exclude_members = no_private || args.members.values().any(|m| m.skip);
     let restore = restore::Manager::new();
+    if !args.dry_run {
+        restore.set_journal(restore::journal_dir(&metadata.target_directory))?;
+    }
     let workspace_root = &metadata.workspace_root;
     let root_manifest = &workspace_root.join("Cargo.toml");
     let mut root_crate = None;
     let mut private_crates = BTreeSet::new();
-    let modify_deps = |doc: &mut toml_edit::DocumentMut, manifest_path: &Path| {
+    // Per-member config takes precedence over --detach-path-deps.
+    let detach_mode = |id: PackageId| {
+        args.members
+            .get(&metadata[id].name)
+            .and_then(|m| m.detach_path_deps)
+            .unwrap_or(args.detach_path_deps)
+    };
+    let write = |orig: String, new: String, manifest_path: &Path| -> Result<()> {
+        if args.dry_run {
+            let display_path = manifest_path.strip_prefix(workspace_root).unwrap_or(manifest_path);
+            print!("{}", diff::unified(&display_path.to_string_lossy(), &orig, &new));
+            return Ok(());
+        }
+        restore.write(manifest_path, orig, new.as_bytes())
+    };
+    let modify_deps = |doc: &mut toml_edit::DocumentMut, id: PackageId| {
+        let package = &metadata[id];
         if term::verbose() {
-            info!("modifying dependencies in {}", manifest_path.display());
+            info!("modifying dependencies in {}", package.manifest_path.display());
         }
-        remove_dev_deps(doc);
-        if let Some(mode) = args.detach_path_deps {
+        if no_dev_deps {
+            remove_dev_deps(doc);
+        }
+        if let Some(mode) = detach_mode(id) {
             detach_path_deps(doc, mode);
         }
     };
@@ -102,35 +154,51 @@ pub(crate) fn with(
             manifest = Some(m);
             is_private
         };
-        if is_private && no_private {
-            if is_root {
-                bail!("--no-private is not supported yet with workspace with private root crate");
-            }
+        let skip = args.members.get(&package.name).is_some_and(|m| m.skip);
+        if is_private && no_private || skip {
+            // If this is the root crate, the root manifest is turned into a
+            // virtual manifest in the if block after loop.
             private_crates.insert(manifest_path);
-        } else if is_root && no_private {
-            root_crate = Some(manifest);
+        } else if is_root && (exclude_members || only_member.is_some()) {
+            root_crate = Some((id, manifest));
             // This case is handled in the if block after loop.
-        } else if no_dev_deps {
+        } else if no_dev_deps || detach_mode(id).is_some() {
             let manifest = match manifest {
                 Some(manifest) => manifest,
                 None => Manifest::new(manifest_path, metadata.cargo_version)?,
             };
             let mut doc = manifest.doc;
-            modify_deps(&mut doc, manifest_path);
-            restore.register(manifest.raw, manifest_path);
-            fs::write(manifest_path, doc.to_string())?;
-        } else if args.detach_path_deps.is_some() {
-            bail!(
-                "--detach-path-deps is currently unsupported on subcommand that requires dev-dependencies: {}",
-                args.subcommand.as_str()
-            );
+            modify_deps(&mut doc, id);
+            write(manifest.raw, doc.to_string(), manifest_path)?;
         }
     }
+    let members: Vec<&Path> = metadata
+        .workspace_members
+        .iter()
+        .map(|&id| &*metadata[id].manifest_path)
+        .filter(|p| !private_crates.contains(p))
+        .collect();
+    if members.is_empty() {
+        bail!("no crates to check remain in the workspace; all members are private or skipped");
+    }
     let has_root_crate = root_crate.is_some();
-    if no_private && (no_dev_deps && has_root_crate || !private_crates.is_empty()) {
+    let modifies_root_crate =
+        root_crate.as_ref().is_some_and(|&(id, _)| no_dev_deps || detach_mode(id).is_some());
+    let only_member = only_member.map(|id| &*metadata[id].manifest_path);
+    if has_root_crate && only_member.is_some_and(|m| m != root_manifest) {
+        warn!(
+            "root package {} is always a member of the workspace, so its dependencies are also \
+             considered when resolving minimal versions for other members",
+            root_manifest.display()
+        );
+    }
+    if exclude_members && (modifies_root_crate || !private_crates.is_empty())
+        || only_member.is_some()
+    {
         let manifest_path = root_manifest;
+        let root_id = root_crate.as_ref().map(|&(id, _)| id);
         let (mut doc, orig) = match root_crate {
-            Some(Some(manifest)) => (manifest.doc, manifest.raw),
+            Some((_, Some(manifest))) => (manifest.doc, manifest.raw),
             _ => {
                 let orig = fs::read_to_string(manifest_path)?;
                 (
@@ -141,35 +209,99 @@ pub(crate) fn with(
                 )
             }
         };
-        if no_dev_deps && has_root_crate {
-            modify_deps(&mut doc, manifest_path);
+        if let Some(id) = root_id.filter(|_| modifies_root_crate) {
+            modify_deps(&mut doc, id);
         }
-        if !private_crates.is_empty() {
+        if let Some(member) = only_member {
+            if term::verbose() {
+                info!("making {} the only member of the workspace", member.display());
+            }
+            set_only_member(&mut doc, workspace_root, member);
+            if private_crates.contains(&**root_manifest) {
+                make_virtual(&mut doc);
+            }
+        } else if !private_crates.is_empty() {
             if term::verbose() {
-                info!("removing private crates from {}", manifest_path.display());
+                info!("removing private or skipped crates from {}", manifest_path.display());
+            }
+            remove_private_crates(&mut doc, workspace_root, &members, &private_crates);
+            // Cargo does not exclude a crate in the directory of another member
+            // if it is reached as a path dependency of a member, so it remains a
+            // member. Remove its dev-dependencies instead, which are not needed
+            // since it is not checked.
+            for nested in nested_private_crates(root_manifest, &members, &private_crates) {
+                let manifest = Manifest::new(nested, metadata.cargo_version)?;
+                let mut doc = manifest.doc;
+                remove_dev_deps(&mut doc);
+                write(manifest.raw, doc.to_string(), nested)?;
             }
-            remove_private_crates(&mut doc, workspace_root, private_crates);
         }
-        restore.register(orig, manifest_path);
-        fs::write(manifest_path, doc.to_string())?;
+        write(orig, doc.to_string(), manifest_path)?;
+    }
+    if args.dry_run {
+        // Print the commands to be run without running them.
+        return f();
     }
-    if restore_lockfile {
-        let lockfile = &workspace_root.join("Cargo.lock");
+    let lockfile = &workspace_root.join("Cargo.lock");
+    let orig_lockfile = if lockfile.exists() { Some(fs::read(lockfile)?) } else { None };
+    // Register the lockfile even if --keep-lockfile is passed, to compare it
+    // with the minimized one (--report), and to restore it on interruption.
+    // If there is no lockfile (e.g., it is gitignored), the one created by
+    // `cargo update` is removed.
+    match &orig_lockfile {
+        Some(orig) => restore.register(lockfile, orig.clone())?,
+        None => restore.register_created(lockfile)?,
+    }
+    let res = f();
+
+    if !restore_lockfile {
+        restore.forget(lockfile);
+    }
+
+    if intermediate {
+        // The lockfile is restored below.
+    } else if let Some(lockfile_out) = &args.lockfile_out {
         if lockfile.exists() {
-            restore.register(fs::read(lockfile)?, lockfile);
+            info!("writing Cargo.lock with minimal versions to {lockfile_out}");
+            fs::write(lockfile_out, fs::read(lockfile)?)?;
+        }
+    } else if res.is_err() && restore_lockfile && !process::terminating() {
+        // Save Cargo.lock with minimal versions to make it easy to see which
+        // versions were picked when the build failed. If there was no
+        // Cargo.lock, the one created while running is still removed.
+        if fs::read(lockfile).is_ok_and(|new| orig_lockfile.as_ref() != Some(&new)) {
+            save_failed_lockfile(ws, lockfile);
         }
     }
-    // TODO: emit error if there is no remaining crates due to --no-private (currently error is emitted by cargo update -Z ...)
-
-    f()?;
 
     // Restore original Cargo.toml and Cargo.lock.
     restore.restore_all();
 
-    Ok(())
+    res
 }
 
-fn remove_dev_deps(doc: &mut toml_edit::DocumentMut) {
+/// Copies Cargo.lock with minimal versions to the target directory, so that it
+/// is kept after the original Cargo.lock is restored.
+fn save_failed_lockfile(ws: &Workspace, lockfile: &Path) {
+    let out = &ws.failed_lockfile_path();
+    let res = (|| {
+        fs::create_dir_all(out.parent().unwrap())?;
+        fs::write(out, fs::read(lockfile)?)
+    })();
+    match res {
+        Ok(()) => info!(
+            "the build failed; Cargo.lock with minimal versions has been saved to {}",
+            out.display()
+        ),
+        Err(e) => warn!("failed to save Cargo.lock with minimal versions: {e:#}"),
+    }
+}
+
+/// Removes dev-dependencies, and dev-dependency-only entries in `[features]`.
+///
+/// This prevents dev-dependencies from raising the minimal versions of
+/// normal dependencies.
+pub fn remove_dev_deps(doc: &mut toml_edit::DocumentMut) {
     // Collect dependency names from [dependencies], [build-dependencies], [target.'...'.dependencies], and [target.'...'.build-dependencies].
     let mut keeping_features = HashSet::new();
     let mut collect_features = |table: &dyn toml_edit::TableLike| {
@@ -237,64 +369,171 @@ fn remove_dev_deps(doc: &mut toml_edit::DocumentMut) {
     }
 }
 
-fn remove_private_crates(
+/// Removes the given crates from the workspace.
+///
+/// `members` and `private_crates` are paths to the manifests of the remaining
+/// workspace members and of the crates to remove. `workspace.members` is
+/// replaced with the remaining members, which also expands globs that match
+/// both public and private crates, and the private crates are added to
+/// `workspace.exclude` so that they do not become members again as path
+/// dependencies. If the root crate is removed, the root manifest is turned
+/// into a virtual manifest.
+///
+/// Note that cargo ignores `workspace.exclude` for crates in the directory of
+/// another member, so such crates still become members if they are reached as
+/// path dependencies of the remaining members.
+pub fn remove_private_crates(
     doc: &mut toml_edit::DocumentMut,
     workspace_root: &Path,
-    mut private_crates: BTreeSet<&Path>,
+    members: &[&Path],
+    private_crates: &BTreeSet<&Path>,
 ) {
-    let table = doc.as_table_mut();
-    if let Some(workspace) = table.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut)
+    let root_manifest = &workspace_root.join("Cargo.toml");
+    let removes_root = private_crates.contains(&**root_manifest);
+    let Some(workspace) = doc.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut)
+    else {
+        return;
+    };
+    // The root crate is always a member unless the manifest is virtual.
+    let new_members: toml_edit::Array = members
+        .iter()
+        .filter(|&&m| m != root_manifest)
+        .map(|m| relative_dir(workspace_root, m))
+        .collect();
+    workspace.insert("members", toml_edit::value(new_members));
+    let private_dirs: Vec<String> =
+        private_crates.iter().map(|p| relative_dir(workspace_root, p)).collect();
+    if let Some(default_members) =
+        workspace.get_mut("default-members").and_then(toml_edit::Item::as_array_mut)
     {
-        if let Some(members) = workspace.get_mut("members").and_then(toml_edit::Item::as_array_mut)
-        {
-            let mut i = 0;
-            while i < members.len() {
-                if let Some(member) = members.get(i).and_then(toml_edit::Value::as_str) {
-                    let manifest_path = workspace_root.join(member).join("Cargo.toml");
-                    if let Some(p) = private_crates.iter().find_map(|p| {
-                        same_file::is_same_file(p, &manifest_path)
-                            .ok()
-                            .and_then(|v| if v { Some(*p) } else { None })
-                    }) {
-                        members.remove(i);
-                        private_crates.remove(p);
-                        continue;
-                    }
-                }
-                i += 1;
+        default_members.retain(|m| {
+            m.as_str().is_none_or(|m| {
+                let m = m.trim_start_matches("./").trim_end_matches('/');
+                let m = if m == "." { "" } else { m };
+                !private_dirs.iter().any(|p| p == m)
+            })
+        });
+        if default_members.is_empty() {
+            workspace.remove("default-members");
+        } else {
+            default_members.fmt();
+        }
+    }
+    // The root directory cannot be excluded; it is handled by making the
+    // manifest virtual.
+    let excludes = private_dirs.into_iter().filter(|p| !p.is_empty());
+    match workspace.get_mut("exclude").and_then(toml_edit::Item::as_array_mut) {
+        Some(exclude) => exclude.extend(excludes),
+        None => {
+            let exclude: toml_edit::Array = excludes.collect();
+            if !exclude.is_empty() {
+                workspace.insert("exclude", toml_edit::value(exclude));
             }
         }
-        if private_crates.is_empty() {
-            return;
+    }
+    if removes_root {
+        make_virtual(doc);
+    }
+}
+
+/// Returns the crates in `private_crates` that are in the directory of a member
+/// other than the root crate, which cannot be excluded from the workspace by
+/// [`remove_private_crates`] if they are reached as path dependencies.
+fn nested_private_crates<'a>(
+    root_manifest: &Path,
+    members: &[&Path],
+    private_crates: &BTreeSet<&'a Path>,
+) -> Vec<&'a Path> {
+    private_crates
+        .iter()
+        .copied()
+        .filter(|&p| {
+            p != root_manifest
+                && members.iter().any(|&m| {
+                    m != root_manifest && p.parent().unwrap().starts_with(m.parent().unwrap())
+                })
+        })
+        .collect()
+}
+
+/// Turns the root manifest into a virtual manifest by removing the package
+/// and its targets and dependencies, keeping the `[workspace]` table.
+fn make_virtual(doc: &mut toml_edit::DocumentMut) {
+    // Virtual manifests have no edition to infer the resolver from, so keep
+    // the resolver used by the root package.
+    let package = doc.get("package").and_then(toml_edit::Item::as_table_like);
+    let resolver = package.and_then(|p| p.get("resolver")).and_then(toml_edit::Item::as_str);
+    let resolver = resolver.map(str::to_owned).or_else(|| {
+        let edition = package.and_then(|p| p.get("edition"))?;
+        let edition = match edition.as_str() {
+            Some(edition) => edition,
+            // edition.workspace = true
+            None => doc.get("workspace")?.get("package")?.get("edition")?.as_str()?,
+        };
+        match edition {
+            "2021" => Some("2".to_owned()),
+            "2024" => Some("3".to_owned()),
+            _ => None,
         }
-        // Handles the case that the members field contains glob.
-        // TODO: test that it also works when public and private crates are nested.
-        if let Some(exclude) = workspace.get_mut("exclude").and_then(toml_edit::Item::as_array_mut)
-        {
-            for private_crate in private_crates {
-                exclude.push(private_crate.parent().unwrap().to_str().unwrap());
+    });
+    let table = doc.as_table_mut();
+    for key in [
+        "package",
+        "lib",
+        "bin",
+        "example",
+        "test",
+        "bench",
+        "dependencies",
+        "dev-dependencies",
+        "build-dependencies",
+        "target",
+        "features",
+        "badges",
+        "lints",
+    ] {
+        table.remove(key);
+    }
+    if let Some(workspace) = table.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut)
+    {
+        if let Some(resolver) = resolver {
+            if !workspace.contains_key("resolver") {
+                workspace.insert("resolver", toml_edit::value(resolver));
             }
-        } else {
-            workspace.insert(
-                "exclude",
-                toml_edit::Item::Value(toml_edit::Value::Array(
-                    private_crates
-                        .iter()
-                        .map(|p| {
-                            toml_edit::Value::String(toml_edit::Formatted::new(
-                                p.parent().unwrap().to_str().unwrap().to_owned(),
-                            ))
-                        })
-                        .collect::<toml_edit::Array>(),
-                )),
-            );
         }
     }
 }
 
-fn detach_path_deps(doc: &mut toml_edit::DocumentMut, mode: DetachPathDeps) {
-    // --detach-path-deps is currently only supported for subcommands that call remove_dev_deps.
-    const KIND: &[&str] = &["build-dependencies", "dependencies"];
+/// Returns the directory of the given manifest relative to the workspace
+/// root, in the form used in `workspace.members`.
+fn relative_dir(workspace_root: &Path, manifest_path: &Path) -> String {
+    let dir = manifest_path.parent().unwrap();
+    // Members are always separated by `/`, regardless of the platform.
+    let rel = dir.strip_prefix(workspace_root).unwrap_or(dir);
+    rel.iter().map(|s| s.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn set_only_member(doc: &mut toml_edit::DocumentMut, workspace_root: &Path, member: &Path) {
+    let Some(workspace) = doc.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut)
+    else {
+        return;
+    };
+    let mut members = toml_edit::Array::new();
+    if member.parent().unwrap() != workspace_root {
+        members.push(relative_dir(workspace_root, member));
+    }
+    workspace.insert("members", toml_edit::value(members));
+    workspace.remove("default-members");
+}
+
+/// Removes `path` fields from dependencies that have `version` or `git`
+/// field, so that they are resolved from the registry or git as after
+/// publishing.
+///
+/// Dev-dependencies are also detached, so that tests run against the
+/// published versions of sibling crates.
+pub fn detach_path_deps(doc: &mut toml_edit::DocumentMut, mode: DetachPathDeps) {
+    const KIND: &[&str] = &["build-dependencies", "dependencies", "dev-dependencies"];
     fn remove_path(deps: &mut toml_edit::Item, mode: DetachPathDeps) {
         if let Some(deps) = deps.as_table_like_mut() {
             for (_name, dep) in deps.iter_mut() {
@@ -609,14 +848,13 @@ l = { version = '=1.2.3-alpha.1'}
 [build-dependencies]
 b = { version = '1' }
 [dev-dependencies]
-c = { path = 'p', version = '1' }
+c = { version = '1' }
 [dependencies.d]
 version = '1'
 [build-dependencies.e]
 version = '1'
 [dev-dependencies.f]
 version = '1'
-path = 'p'
 "
         );
 
@@ -658,14 +896,13 @@ l = { version = '=1.2.3-alpha.1'}
 [target.b.build-dependencies]
 b = { version = '1' }
 [target.c.dev-dependencies]
-c = { path = 'p', version = '1' }
+c = { version = '1' }
 [target.c.dependencies.d]
 version = '1'
 [target.b.build-dependencies.e]
 version = '1'
 [target.a.dev-dependencies.f]
 version = '1'
-path = 'p'
 "
         );
 
@@ -699,4 +936,341 @@ version = '1'
 "
         );
     }
+
+    mod remove_private_crates {
+        use std::{collections::BTreeSet, env, path::Path, process};
+
+        use serde_json::Value;
+
+        use crate::fs::test_dir;
+
+        macro_rules! test {
+            ($name:ident, $members:expr, $private:expr, $input:expr, $expected:expr) => {
+                #[test]
+                fn $name() {
+                    let root = Path::new("/ws");
+                    let manifest = |dir: &str| {
+                        if dir.is_empty() {
+                            root.join("Cargo.toml")
+                        } else {
+                            root.join(dir).join("Cargo.toml")
+                        }
+                    };
+                    let members: Vec<_> = $members.iter().map(|d| manifest(d)).collect();
+                    let members: Vec<&Path> = members.iter().map(|p| &**p).collect();
+                    let private: Vec<_> = $private.iter().map(|d| manifest(d)).collect();
+                    let private: BTreeSet<&Path> = private.iter().map(|p| &**p).collect();
+                    let mut doc = $input.parse().unwrap();
+                    super::super::remove_private_crates(&mut doc, root, &members, &private);
+                    assert_eq!($expected, doc.to_string());
+                }
+            };
+        }
+
+        test!(
+            explicit,
+            ["", "a"],
+            ["b"],
+            "\
+[package]
+name = 'root'
+[workspace]
+members = ['a', 'b']
+",
+            "\
+[package]
+name = 'root'
+[workspace]
+members = [\"a\"]
+exclude = [\"b\"]
+"
+        );
+
+        test!(
+            glob,
+            ["crates/a", "crates/c"],
+            ["crates/b"],
+            "\
+[workspace]
+members = ['crates/*']
+exclude = ['x']
+default-members = ['crates/a', 'crates/b']
+",
+            "\
+[workspace]
+members = [\"crates/a\", \"crates/c\"]
+exclude = ['x', \"crates/b\"]
+default-members = ['crates/a']
+"
+        );
+
+        test!(
+            nested,
+            ["a", "b/c"],
+            ["a/b", "b"],
+            "\
+[workspace]
+members = ['a', 'a/b', 'b', 'b/*']
+",
+            "\
+[workspace]
+members = [\"a\", \"b/c\"]
+exclude = [\"a/b\", \"b\"]
+"
+        );
+
+        test!(
+            private_root,
+            ["a"],
+            [""],
+            "\
+cargo-features = []
+[package]
+name = 'root'
+edition = '2021'
+publish = false
+[dependencies]
+a = { path = 'a' }
+[dev-dependencies]
+[[bin]]
+name = 'b'
+[features]
+f = []
+[target.'cfg(unix)'.dependencies]
+[lints]
+workspace = true
+[workspace]
+default-members = ['.', 'a']
+[workspace.lints.rust]
+[profile.dev]
+",
+            "\
+cargo-features = []
+[workspace]
+default-members = ['a']
+members = [\"a\"]
+resolver = \"2\"
+[workspace.lints.rust]
+[profile.dev]
+"
+        );
+
+        test!(
+            private_root_inherited_edition,
+            ["a"],
+            [""],
+            "\
+[package]
+edition.workspace = true
+[workspace]
+members = ['a']
+[workspace.package]
+edition = '2024'
+",
+            "\
+[workspace]
+members = [\"a\"]
+resolver = \"3\"
+[workspace.package]
+edition = '2024'
+"
+        );
+
+        // A private crate in the directory of a public member is not excluded
+        // by cargo if it is a path dependency of the member, so its
+        // dev-dependencies are removed instead.
+        #[test]
+        fn nested_path_dep() {
+            let tmp = test_dir();
+            let dir = tmp.path();
+            let write = |path: &str, contents: &str| {
+                let path = dir.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            };
+            let package = |name: &str, publish: bool, dev_deps: &str| {
+                format!(
+                    "[package]\nname = '{name}'\nversion = '0.1.0'\nedition = '2021'\n\
+                     publish = {publish}\n[dev-dependencies]\n{dev_deps}"
+                )
+            };
+            write("Cargo.toml", "[workspace]\nmembers = ['a', 'a/b', 'c']\nresolver = '2'\n");
+            write("a/Cargo.toml", &package("a", true, "b = { path = 'b' }\n"));
+            write("a/b/Cargo.toml", &package("b", false, "d = { path = '../../d' }\n"));
+            write("c/Cargo.toml", &package("c", false, "d = { path = '../d' }\n"));
+            write("d/Cargo.toml", &package("d", false, ""));
+            for krate in ["a", "a/b", "c", "d"] {
+                write(&format!("{krate}/src/lib.rs"), "");
+            }
+
+            let root_manifest = &dir.join("Cargo.toml");
+            let a = &dir.join("a/Cargo.toml");
+            let b = &dir.join("a/b/Cargo.toml");
+            let c = &dir.join("c/Cargo.toml");
+            let members: Vec<&Path> = vec![a];
+            let private: BTreeSet<&Path> = [&**b, &**c].into_iter().collect();
+            let mut doc = std::fs::read_to_string(root_manifest).unwrap().parse().unwrap();
+            super::super::remove_private_crates(&mut doc, dir, &members, &private);
+            std::fs::write(root_manifest, doc.to_string()).unwrap();
+            let nested = super::super::nested_private_crates(root_manifest, &members, &private);
+            assert_eq!(nested, [&**b]);
+            for path in nested {
+                let mut doc = std::fs::read_to_string(path).unwrap().parse().unwrap();
+                super::super::remove_dev_deps(&mut doc);
+                std::fs::write(path, doc.to_string()).unwrap();
+            }
+
+            let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+            let output = process::Command::new(cargo)
+                .args(["metadata", "--format-version=1", "--offline", "--manifest-path"])
+                .arg(root_manifest)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            let metadata: Value = serde_json::from_slice(&output.stdout).unwrap();
+            let packages = metadata["packages"].as_array().unwrap();
+            let name = |id: &Value| {
+                packages.iter().find(|p| p["id"] == *id).unwrap()["name"].as_str().unwrap()
+            };
+            let mut members: Vec<&str> =
+                metadata["workspace_members"].as_array().unwrap().iter().map(name).collect();
+            members.sort_unstable();
+            let mut resolved: Vec<&str> = packages.iter().map(|p| name(&p["id"])).collect();
+            resolved.sort_unstable();
+            // b is still a member as a path dependency of a, but the
+            // dev-dependencies of b no longer affect the resolution.
+            assert_eq!(members, ["a", "b"]);
+            assert_eq!(resolved, ["a", "b"]);
+        }
+    }
+
+    mod set_only_member {
+        use std::{env, path::Path, process};
+
+        use serde_json::Value;
+
+        use crate::fs::test_dir;
+
+        macro_rules! test {
+            ($name:ident, $member:expr, $input:expr, $expected:expr) => {
+                #[test]
+                fn $name() {
+                    let root = Path::new("/ws");
+                    let member = root.join($member).join("Cargo.toml");
+                    let mut doc = $input.parse().unwrap();
+                    super::super::set_only_member(&mut doc, root, &member);
+                    assert_eq!($expected, doc.to_string());
+                }
+            };
+        }
+
+        test!(
+            member,
+            "crates/b",
+            "\
+[workspace]
+members = ['crates/*']
+default-members = ['crates/a', 'crates/b']
+exclude = ['crates/x']
+",
+            "\
+[workspace]
+members = [\"crates/b\"]
+exclude = ['crates/x']
+"
+        );
+
+        test!(
+            root_crate,
+            "",
+            "\
+[package]
+name = 'root'
+[workspace]
+members = ['a', 'b']
+default-members = ['.']
+",
+            "\
+[package]
+name = 'root'
+[workspace]
+members = []
+"
+        );
+
+        test!(
+            inline,
+            "a",
+            "\
+workspace = { members = ['a', 'b'], exclude = ['c'] }
+",
+            "\
+workspace = { members = [\"a\"], exclude = ['c'] }
+"
+        );
+
+        test!(
+            no_workspace,
+            "",
+            "\
+[package]
+name = 'root'
+",
+            "\
+[package]
+name = 'root'
+"
+        );
+
+        #[test]
+        fn workspace_members() {
+            let tmp = test_dir();
+            let dir = tmp.path();
+            let write = |path: &str, contents: &str| {
+                let path = dir.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            };
+            let package = |name: &str| {
+                format!("[package]\nname = '{name}'\nversion = '0.1.0'\nedition = '2021'\n")
+            };
+            write(
+                "Cargo.toml",
+                "[workspace]\nmembers = ['crates/*']\ndefault-members = ['crates/a']\n\
+                 exclude = ['crates/x']\nresolver = '2'\n",
+            );
+            for krate in ["a", "b", "c"] {
+                write(&format!("crates/{krate}/Cargo.toml"), &package(krate));
+                write(&format!("crates/{krate}/src/lib.rs"), "");
+            }
+
+            let root_manifest = &dir.join("Cargo.toml");
+            let mut doc = std::fs::read_to_string(root_manifest).unwrap().parse().unwrap();
+            super::super::set_only_member(&mut doc, dir, &dir.join("crates/b/Cargo.toml"));
+            std::fs::write(root_manifest, doc.to_string()).unwrap();
+
+            let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+            let output = process::Command::new(cargo)
+                .args(["metadata", "--format-version=1", "--offline", "--no-deps"])
+                .arg("--manifest-path")
+                .arg(root_manifest)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            let metadata: Value = serde_json::from_slice(&output.stdout).unwrap();
+            let names = |key: &str| -> Vec<&str> {
+                metadata[key]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|id| {
+                        let packages = metadata["packages"].as_array().unwrap();
+                        packages.iter().find(|p| p["id"] == *id).unwrap()["name"].as_str().unwrap()
+                    })
+                    .collect()
+            };
+            assert_eq!(names("workspace_members"), ["b"]);
+            assert_eq!(names("workspace_default_members"), ["b"]);
+        }
+    }
 }
@@ -16,7 +16,7 @@ type Object = Map<String, Value>;
 type ParseResult<T> = Result<T, &'static str>;
 
 /// An opaque unique identifier for referring to the package.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub(crate) struct PackageId {
     index: usize,
@@ -32,6 +32,10 @@ pub(crate) struct Metadata {
     pub(crate) workspace_members: Box<[PackageId]>,
     /// The absolute path to the root of the workspace.
     pub(crate) workspace_root: PathBuf,
+    /// The absolute path to the build directory where Cargo places its output.
+    pub(crate) target_directory: PathBuf,
+    /// The workspace-level metadata specified in `[workspace.metadata]`.
+    pub(crate) workspace_metadata: Value,
 }
 
 impl Metadata {
@@ -53,6 +57,12 @@ impl Metadata {
             .map_err(|s| format_err!("failed to parse `{s}` field from metadata"))
     }
 
+    /// Parses the output of `cargo metadata` of the latest cargo.
+    #[cfg(test)]
+    pub(crate) fn from_json(json: &Value) -> Self {
+        Self::from_obj(json.as_object().unwrap().clone(), u32::MAX).unwrap()
+    }
+
     fn from_obj(mut map: Object, cargo_version: u32) -> ParseResult<Self> {
         let raw_packages = map.remove_array("packages")?;
         let mut packages = Vec::with_capacity(raw_packages.len());
@@ -75,6 +85,9 @@ impl Metadata {
             packages: packages.into_boxed_slice(),
             workspace_members,
             workspace_root: map.remove_string("workspace_root")?,
+            target_directory: map.remove_string("target_directory")?,
+            // This field is null if `[workspace.metadata]` is not specified.
+            workspace_metadata: map.remove("metadata").unwrap_or_default(),
         })
     }
 }
@@ -88,12 +101,24 @@ impl ops::Index<PackageId> for Metadata {
 }
 
 pub(crate) struct Package {
+    /// The name of the package.
+    pub(crate) name: String,
+    /// The version of the package.
+    pub(crate) version: String,
     /// Absolute path to this package's manifest.
     pub(crate) manifest_path: Box<Path>,
     /// List of registries to which this package may be published.
     ///
     /// This is always `true` if running with a version of Cargo older than 1.39.
     pub(crate) publish: bool,
+    /// List of dependencies of this particular package.
+    pub(crate) dependencies: Vec<Dependency>,
+    /// The minimum supported Rust version of this package.
+    ///
+    /// This is always `None` if running with a version of Cargo older than 1.58.
+    pub(crate) rust_version: Option<String>,
+    /// The contents of `[package.metadata]`.
+    pub(crate) metadata: Value,
 }
 
 impl Package {
@@ -102,6 +127,8 @@ impl Package {
 
         let id = map.remove_string("id")?;
         Ok((id, Self {
+            name: map.remove_string("name")?,
+            version: map.remove_string("version")?,
             manifest_path: map.remove_string::<PathBuf>("manifest_path")?.into_boxed_path(),
             // This field was added in Rust 1.39.
             publish: if cargo_version >= 39 {
@@ -110,10 +137,51 @@ impl Package {
             } else {
                 true
             },
+            dependencies: map
+                .remove_array("dependencies")?
+                .into_iter()
+                .map(Dependency::from_value)
+                .collect::<Result<_, _>>()?,
+            // This field is null if `[package.metadata]` is not specified.
+            metadata: map.remove("metadata").unwrap_or_default(),
+            // This field was added in Rust 1.58.
+            rust_version: if cargo_version >= 58 {
+                map.remove_nullable("rust_version", into_string)?
+            } else {
+                None
+            },
         }))
     }
 }
 
+pub(crate) struct Dependency {
+    /// The name of the dependency.
+    pub(crate) name: String,
+    /// The version requirement for the dependency.
+    pub(crate) req: String,
+    /// The dependency kind.
+    ///
+    /// "dev", "build", or null for a normal dependency.
+    pub(crate) kind: Option<String>,
+    /// The source ID of the dependency.
+    ///
+    /// This is null for path dependencies.
+    pub(crate) source: Option<String>,
+}
+
+impl Dependency {
+    fn from_value(mut value: Value) -> ParseResult<Self> {
+        let map = value.as_object_mut().ok_or("dependencies")?;
+
+        Ok(Self {
+            name: map.remove_string("name")?,
+            req: map.remove_string("req")?,
+            kind: map.remove_nullable("kind", into_string)?,
+            source: map.remove_nullable("source", into_string)?,
+        })
+    }
+}
+
 #[allow(clippy::option_option)]
 fn allow_null<T>(value: Value, f: impl FnOnce(Value) -> Option<T>) -> Option<Option<T>> {
     if value.is_null() { Some(None) } else { f(value).map(Some) }
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --no-modify.
+//
+// Resolves minimal versions into a lockfile in the target directory, using
+// cargo's unstable support for lockfiles at another path, instead of
+// modifying Cargo.toml and Cargo.lock in the workspace.
+
+use anyhow::Result;
+
+use crate::{cargo::Workspace, cli::Args, fs};
+
+/// Returns the reason why --no-modify cannot be used, if any.
+pub(crate) fn unsupported(
+    ws: &Workspace,
+    args: &Args,
+    remove_dev_deps: bool,
+) -> Option<&'static str> {
+    let Some(support) = &ws.no_modify else {
+        return Some("by the current cargo (nightly cargo is required)");
+    };
+    if support.lockfile_path.is_none() {
+        return Some("by the current cargo, which does not support `resolver.lockfile-path`");
+    }
+    if remove_dev_deps && !support.avoid_dev_deps {
+        return Some("by the current cargo, which does not support `-Z avoid-dev-deps`");
+    }
+    // These need to modify Cargo.toml.
+    if args.no_private || args.members.values().any(|m| m.skip) {
+        return Some("with --ignore-private or `skip` in config");
+    }
+    if args.detach_path_deps.is_some()
+        || args.members.values().any(|m| matches!(m.detach_path_deps, Some(Some(_))))
+    {
+        return Some("with --detach-path-deps");
+    }
+    // These need Cargo.lock in the workspace root.
+    if args.per_package_lockfile {
+        return Some("with --per-package-lockfile");
+    }
+    if args.rust_version {
+        return Some("with --rust-version");
+    }
+    if !args.pins.is_empty() {
+        return Some("with --pin");
+    }
+    if args.two_phase && !remove_dev_deps {
+        return Some("with --two-phase");
+    }
+    if args.report.is_some() {
+        return Some("with --report");
+    }
+    if args.keep_lockfile || args.lockfile_out.is_some() {
+        return Some("with --keep-lockfile or --lockfile-out");
+    }
+    None
+}
+
+/// Makes `ws` use a lockfile in the target directory while running `f`.
+pub(crate) fn with(
+    ws: &mut Workspace,
+    args: &Args,
+    remove_dev_deps: bool,
+    f: impl FnOnce(&Workspace) -> Result<()>,
+) -> Result<()> {
+    let dir = ws.metadata.target_directory.join("minimal-versions/lockfile");
+    let lockfile = dir.join("Cargo.lock");
+    if !args.dry_run {
+        fs::create_dir_all(&dir)?;
+        // Always resolve from scratch.
+        fs::remove_file_if_exists(&lockfile)?;
+    }
+    if remove_dev_deps {
+        // `cargo update` resolves dev-dependencies even with `-Z avoid-dev-deps`,
+        // so resolve minimal versions in the build step, which skips them.
+        // The lockfile is not written in this case.
+        ws.avoid_dev_deps =
+            Some(if args.direct { "direct-minimal-versions" } else { "minimal-versions" });
+    }
+    ws.lockfile = Some(lockfile);
+    f(ws)
+}
@@ -3,13 +3,19 @@
 use std::{
     cell::Cell,
     ffi::OsStr,
-    fmt,
+    fmt, io,
     path::Path,
-    process::{Command, ExitStatus, Output},
+    process::{Child, Command, ExitStatus, Output, Stdio},
     str,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context as _, Error, Result};
+use anyhow::{Context as _, Error, Result, bail};
 
 use crate::term;
 
@@ -23,17 +29,124 @@ macro_rules! cmd {
     }};
 }
 
+/// The IDs of running child processes.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+/// Set when the process is terminating by a signal, to not spawn new processes.
+static TERMINATING: AtomicBool = AtomicBool::new(false);
+
+/// How long to wait for child processes to exit after interrupting them.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns `true` if the process is terminating by a signal.
+pub(crate) fn terminating() -> bool {
+    TERMINATING.load(Ordering::SeqCst)
+}
+
+/// Runs `f` unless the process is terminating by a signal.
+///
+/// This is atomic with respect to `terminate_children`, so files written by `f`
+/// are never written after the signal handler has started restoring them.
+pub(crate) fn unless_terminating<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _children = CHILDREN.lock().unwrap_or_else(PoisonError::into_inner);
+    if terminating() {
+        bail!("interrupted by signal");
+    }
+    f()
+}
+
+/// Terminates running child processes when a signal is received.
+///
+/// Forwards the signal to them, waits for them to exit, and kills the ones
+/// still running after a timeout. No new processes are spawned after this is
+/// called.
+pub(crate) fn terminate_children(signal: i32) {
+    let pids = {
+        let children = CHILDREN.lock().unwrap_or_else(PoisonError::into_inner);
+        TERMINATING.store(true, Ordering::SeqCst);
+        children.clone()
+    };
+    if pids.is_empty() {
+        return;
+    }
+    let running = || {
+        let children = CHILDREN.lock().unwrap_or_else(PoisonError::into_inner);
+        pids.iter().copied().filter(|pid| children.contains(pid)).collect::<Vec<_>>()
+    };
+    let wait = |timeout: Duration| {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && !running().is_empty() {
+            thread::sleep(Duration::from_millis(50));
+        }
+    };
+    // On Windows, child processes attached to the same console receive the
+    // console control event themselves.
+    #[cfg(unix)]
+    if let Some(signal) = rustix::process::Signal::from_named_raw(signal) {
+        for &pid in &pids {
+            send_signal(pid, signal);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = signal;
+    wait(TERMINATE_TIMEOUT);
+    let remaining = running();
+    if remaining.is_empty() {
+        return;
+    }
+    for pid in remaining {
+        warn!("child process {pid} did not exit in time; killing it");
+        kill(pid);
+    }
+    // Wait for the killed processes to be reaped by the main thread.
+    wait(Duration::from_secs(1));
+}
+
+/// Sends the given signal to the given process.
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: rustix::process::Signal) {
+    if let Some(pid) = i32::try_from(pid).ok().and_then(rustix::process::Pid::from_raw) {
+        let _ = rustix::process::kill_process(pid, signal);
+    }
+}
+
+/// Forcibly terminates the given process (and its children on Windows).
+fn kill(pid: u32) {
+    #[cfg(unix)]
+    send_signal(pid, rustix::process::Signal::KILL);
+    #[cfg(not(unix))]
+    {
+        // Without /F, taskkill only sends WM_CLOSE, which console programs ignore.
+        let _ = Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
 // A builder for an external process, inspired by https://github.com/rust-lang/cargo/blob/0.47.0/src/cargo/util/process_builder.rs
 #[must_use]
 pub(crate) struct ProcessBuilder {
     cmd: Command,
     /// `true` to include full program path in display.
     display_program_path: Cell<bool>,
+    /// `true` to include environment variables in display.
+    display_env_vars: Cell<bool>,
 }
 
 impl ProcessBuilder {
     pub(crate) fn from_std(cmd: Command) -> Self {
-        Self { cmd, display_program_path: Cell::new(term::verbose()) }
+        Self {
+            cmd,
+            display_program_path: Cell::new(term::verbose()),
+            display_env_vars: Cell::new(false),
+        }
+    }
+
+    /// Returns the underlying `std::process::Command`.
+    pub(crate) fn into_std(self) -> Command {
+        self.cmd
     }
 
     /// Adds an argument to pass to the program.
@@ -54,15 +167,41 @@ impl ProcessBuilder {
         self
     }
 
+    /// Enables environment variables display.
+    pub(crate) fn display_env_vars(&mut self) -> &mut Self {
+        self.display_env_vars.set(true);
+        self
+    }
+
     /// Enables all display-related flags.
     fn display_all(&self) {
         self.display_program_path.set(true);
+        self.display_env_vars.set(true);
+    }
+
+    /// Spawns a process, and tracks it until `wait` returns, so that it can be
+    /// terminated when a signal is received.
+    fn spawn<T>(&mut self, wait: impl FnOnce(Child) -> io::Result<T>) -> io::Result<T> {
+        let child = {
+            let mut children = CHILDREN.lock().unwrap_or_else(PoisonError::into_inner);
+            if terminating() {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted by signal"));
+            }
+            let child = self.cmd.spawn()?;
+            children.push(child.id());
+            child
+        };
+        let pid = child.id();
+        let res = wait(child);
+        CHILDREN.lock().unwrap_or_else(PoisonError::into_inner).retain(|&p| p != pid);
+        res
     }
 
     /// Executes a process, waiting for completion, and mapping non-zero exit
     /// status to an error.
     pub(crate) fn run(&mut self) -> Result<()> {
-        let status = self.cmd.status().with_context(|| {
+        self.cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let status = self.spawn(|mut child| child.wait()).with_context(|| {
             self.display_all();
             process_error(format!("could not execute process {self}"), None, None)
         })?;
@@ -78,10 +217,20 @@ impl ProcessBuilder {
         }
     }
 
+    /// Executes a process, waiting for completion, and returns its exit status.
+    pub(crate) fn status(&mut self) -> Result<ExitStatus> {
+        self.cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        self.spawn(|mut child| child.wait()).with_context(|| {
+            self.display_all();
+            process_error(format!("could not execute process {self}"), None, None)
+        })
+    }
+
     /// Executes a process, captures its stdio output, returning the captured
     /// output, or an error if non-zero exit status.
     pub(crate) fn run_with_output(&mut self) -> Result<Output> {
-        let output = self.cmd.output().with_context(|| {
+        self.cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = self.spawn(Child::wait_with_output).with_context(|| {
             self.display_all();
             process_error(format!("could not execute process {self}"), None, None)
         })?;
@@ -118,6 +267,14 @@ impl fmt::Display for ProcessBuilder {
             f.write_str("`")?;
         }
 
+        if self.display_env_vars.get() {
+            for (key, val) in self.cmd.get_envs() {
+                if let Some(val) = val {
+                    write!(f, "{}={} ", key.to_string_lossy(), val.to_string_lossy())?;
+                }
+            }
+        }
+
         if self.display_program_path.get() {
             f.write_str(&self.cmd.get_program().to_string_lossy())?;
         } else {
@@ -170,3 +327,16 @@ fn process_error(mut msg: String, status: Option<ExitStatus>, output: Option<&Ou
 
     Error::msg(msg)
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    #[test]
+    fn send_signal() {
+        use std::os::unix::process::ExitStatusExt as _;
+
+        let mut child = std::process::Command::new("sleep").arg("60").spawn().unwrap();
+        super::send_signal(child.id(), rustix::process::Signal::TERM);
+        assert_eq!(child.wait().unwrap().signal(), Some(rustix::process::Signal::TERM.as_raw()));
+    }
+}
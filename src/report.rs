@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --report.
+//
+// Compares Cargo.lock with minimal versions with the original Cargo.lock, to
+// show what versions were actually tested.
+
+use std::{collections::BTreeSet, fmt, path::Path};
+
+use anyhow::{Context as _, Result};
+use semver::Version;
+use serde_json::json;
+
+use crate::{
+    cli::ReportFormat,
+    lockfile::{self, Lockfile},
+    restore,
+};
+
+pub(crate) fn print(workspace_root: &Path, format: ReportFormat) -> Result<()> {
+    let path = &workspace_root.join("Cargo.lock");
+    let Some(orig) = restore::original(path) else {
+        warn!("no original Cargo.lock to compare with; skipping --report");
+        return Ok(());
+    };
+    let orig = String::from_utf8(orig)
+        .with_context(|| format!("original {} is not valid UTF-8", path.display()))?;
+    let orig = Lockfile::from_str(&orig, path)?;
+    let new = Lockfile::new(path)?;
+
+    let changes = changes(&orig, &new);
+    let incompatible = incompatible_versions(&new);
+    match format {
+        ReportFormat::Table => print_table(&changes, &incompatible, &orig),
+        ReportFormat::Json => {
+            let changes: Vec<_> = changes
+                .iter()
+                .map(|c| {
+                    json!({
+                        "name": c.name,
+                        "original": c.original.map(Version::to_string),
+                        "minimal": c.minimal.map(Version::to_string),
+                        "gap": c.gap().to_string(),
+                    })
+                })
+                .collect();
+            let incompatible: Vec<_> = incompatible
+                .iter()
+                .map(|(name, versions)| {
+                    json!({
+                        "name": name,
+                        "versions": versions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                        "new": !has_incompatible_versions(&orig, name),
+                    })
+                })
+                .collect();
+            println!("{}", json!({ "changes": changes, "incompatible_versions": incompatible }));
+        }
+    }
+    Ok(())
+}
+
+struct Change<'a> {
+    name: &'a str,
+    /// `None` if the package is not in the original Cargo.lock.
+    original: Option<&'a Version>,
+    /// `None` if the package is not in Cargo.lock with minimal versions.
+    minimal: Option<&'a Version>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Gap {
+    Major,
+    Minor,
+    Patch,
+    /// Only pre-release or build metadata differs.
+    Other,
+    Added,
+    Removed,
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Major => "major",
+            Self::Minor => "minor",
+            Self::Patch => "patch",
+            Self::Other => "other",
+            Self::Added => "added",
+            Self::Removed => "removed",
+        })
+    }
+}
+
+impl Change<'_> {
+    fn gap(&self) -> Gap {
+        match (self.original, self.minimal) {
+            (Some(a), Some(b)) if a.major != b.major => Gap::Major,
+            (Some(a), Some(b)) if a.minor != b.minor => Gap::Minor,
+            (Some(a), Some(b)) if a.patch != b.patch => Gap::Patch,
+            (Some(_), Some(_)) => Gap::Other,
+            (None, _) => Gap::Added,
+            (_, None) => Gap::Removed,
+        }
+    }
+}
+
+/// Returns packages whose versions differ between the two lockfiles.
+///
+/// Versions of the same package are paired if they are semver-compatible.
+/// The remaining versions are then paired in ascending order, so that a package
+/// moved to a semver-incompatible version is reported as a major (or minor for
+/// 0.x) change instead of a removal and an addition.
+fn changes<'a>(orig: &'a Lockfile, new: &'a Lockfile) -> Vec<Change<'a>> {
+    let names: BTreeSet<&str> =
+        orig.packages.iter().chain(&new.packages).map(|p| &*p.name).collect();
+    let mut changes = vec![];
+    for name in names {
+        let mut old_versions = sorted_versions(orig, name);
+        let mut new_versions = sorted_versions(new, name);
+        old_versions.retain(|v| {
+            let pos = new_versions.iter().position(|n| n == v);
+            if let Some(pos) = pos {
+                new_versions.remove(pos);
+            }
+            pos.is_none()
+        });
+        let mut unpaired = vec![];
+        for old in old_versions {
+            let pos = new_versions.iter().position(|n| lockfile::is_semver_compatible(old, n));
+            match pos {
+                Some(pos) => {
+                    let minimal = new_versions.remove(pos);
+                    changes.push(Change { name, original: Some(old), minimal: Some(minimal) });
+                }
+                None => unpaired.push(old),
+            }
+        }
+        let mut new_versions = new_versions.into_iter();
+        for old in unpaired {
+            changes.push(Change { name, original: Some(old), minimal: new_versions.next() });
+        }
+        for minimal in new_versions {
+            changes.push(Change { name, original: None, minimal: Some(minimal) });
+        }
+    }
+    changes
+}
+
+/// Returns packages that are resolved to multiple semver-incompatible versions.
+fn incompatible_versions(lockfile: &Lockfile) -> Vec<(&str, Vec<&Version>)> {
+    let names: BTreeSet<&str> = lockfile.packages.iter().map(|p| &*p.name).collect();
+    names
+        .into_iter()
+        .filter(|name| has_incompatible_versions(lockfile, name))
+        .map(|name| (name, sorted_versions(lockfile, name)))
+        .collect()
+}
+
+fn has_incompatible_versions(lockfile: &Lockfile, name: &str) -> bool {
+    let versions = sorted_versions(lockfile, name);
+    versions.iter().any(|a| versions.iter().any(|b| !lockfile::is_semver_compatible(a, b)))
+}
+
+fn sorted_versions<'a>(lockfile: &'a Lockfile, name: &str) -> Vec<&'a Version> {
+    let mut versions: Vec<_> =
+        lockfile.packages.iter().filter(|p| p.name == name).map(|p| &p.version).collect();
+    versions.sort_unstable();
+    versions.dedup();
+    versions
+}
+
+fn print_table(changes: &[Change<'_>], incompatible: &[(&str, Vec<&Version>)], orig: &Lockfile) {
+    struct Row<'a>(&'a str, String, String, Gap);
+    impl fmt::Display for Row<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:<24} {:<16} {:<16} {}", self.0, self.1, self.2, self.3)
+        }
+    }
+    let version = |v: Option<&Version>| v.map_or_else(|| "-".to_owned(), ToString::to_string);
+    if changes.is_empty() {
+        println!("no packages changed versions");
+    } else {
+        println!("{:<24} {:<16} {:<16} GAP", "PACKAGE", "ORIGINAL", "MINIMAL");
+        for c in changes {
+            println!("{}", Row(c.name, version(c.original), version(c.minimal), c.gap()));
+        }
+    }
+    if !incompatible.is_empty() {
+        println!();
+        println!("packages resolved to multiple semver-incompatible versions:");
+        for (name, versions) in incompatible {
+            let versions = versions.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let new = if has_incompatible_versions(orig, name) { "" } else { " (new)" };
+            println!("    {name}: {}{new}", versions.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write as _, path::Path};
+
+    use super::{Gap, changes, incompatible_versions};
+    use crate::lockfile::Lockfile;
+
+    fn lockfile(packages: &[(&str, &str)]) -> Lockfile {
+        let mut raw = String::new();
+        for (name, version) in packages {
+            let _ = write!(raw, "[[package]]\nname = \"{name}\"\nversion = \"{version}\"\n");
+        }
+        Lockfile::from_str(&raw, Path::new("Cargo.lock")).unwrap()
+    }
+
+    #[test]
+    fn report() {
+        let orig = lockfile(&[
+            ("a", "1.2.3"),
+            ("b", "0.2.5"),
+            ("b", "0.3.1"),
+            ("c", "1.0.0"),
+            ("d", "2.0.0"),
+        ]);
+        let new = lockfile(&[
+            ("a", "1.0.0"),
+            ("b", "0.2.0"),
+            ("b", "0.3.1"),
+            ("c", "1.0.0"),
+            ("e", "0.1.0"),
+            ("e", "1.0.0"),
+        ]);
+        let changes: Vec<_> = changes(&orig, &new)
+            .iter()
+            .map(|c| {
+                (
+                    c.name,
+                    c.original.map(ToString::to_string),
+                    c.minimal.map(ToString::to_string),
+                    c.gap(),
+                )
+            })
+            .collect();
+        let s = |s: &str| Some(s.to_owned());
+        assert_eq!(changes, [
+            ("a", s("1.2.3"), s("1.0.0"), Gap::Minor),
+            ("b", s("0.2.5"), s("0.2.0"), Gap::Patch),
+            ("d", s("2.0.0"), None, Gap::Removed),
+            ("e", None, s("0.1.0"), Gap::Added),
+            ("e", None, s("1.0.0"), Gap::Added),
+        ]);
+        let incompatible: Vec<_> = incompatible_versions(&new)
+            .into_iter()
+            .map(|(name, versions)| (name, versions.len()))
+            .collect();
+        assert_eq!(incompatible, [("b", 2), ("e", 2)]);
+    }
+
+    #[test]
+    fn incompatible_changes() {
+        let gaps = |orig: &[(&str, &str)], new: &[(&str, &str)]| {
+            changes(&lockfile(orig), &lockfile(new))
+                .iter()
+                .map(|c| {
+                    (
+                        c.original.map(ToString::to_string),
+                        c.minimal.map(ToString::to_string),
+                        c.gap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let s = |s: &str| Some(s.to_owned());
+        assert_eq!(gaps(&[("a", "2.1.0")], &[("a", "1.5.0")]), [(
+            s("2.1.0"),
+            s("1.5.0"),
+            Gap::Major
+        )]);
+        assert_eq!(gaps(&[("a", "0.2.3")], &[("a", "0.1.0")]), [(
+            s("0.2.3"),
+            s("0.1.0"),
+            Gap::Minor
+        )]);
+        // Compatible versions are paired first.
+        assert_eq!(gaps(&[("a", "1.2.0"), ("a", "3.0.0")], &[("a", "1.0.0"), ("a", "2.0.0")]), [
+            (s("1.2.0"), s("1.0.0"), Gap::Minor),
+            (s("3.0.0"), s("2.0.0"), Gap::Major),
+        ]);
+        assert_eq!(gaps(&[("a", "2.0.0")], &[("a", "0.1.0"), ("a", "1.0.0")]), [
+            (s("2.0.0"), s("0.1.0"), Gap::Major),
+            (None, s("1.0.0"), Gap::Added),
+        ]);
+    }
+}
@@ -1,53 +1,305 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
+//! Restoring files modified while running.
+
 // Adapted from https://github.com/taiki-e/cargo-hack
 
 use std::{
-    mem,
-    path::PathBuf,
-    sync::{Arc, Mutex, PoisonError},
+    io, mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError, Weak},
+    time::{Duration, SystemTime},
 };
 
-use anyhow::Result;
+use anyhow::{Context as _, Result, bail, format_err};
+use serde_json::{Value, json};
+
+use crate::{fs, process, term};
 
-use crate::{fs, term};
+const JOURNAL_INDEX: &str = "index.json";
+
+/// A guard that restores files registered to it to their original contents
+/// when dropped.
+///
+/// Each manager has its own list of files. Files registered to all managers
+/// alive in the process are also restored by the signal handler set by
+/// [`set_signal_handler`], or by [`restore_all`].
+pub struct Manager {
+    state: Arc<Mutex<State>>,
+}
 
-#[derive(Clone)]
-pub(crate) struct Manager {
+#[derive(Default)]
+struct State {
     /// Information on files that need to be restored.
-    files: Arc<Mutex<Vec<File>>>,
+    files: Vec<File>,
+    /// The directory to which the original contents are written, so that they
+    /// can be restored by `cargo minimal-versions restore` even if this
+    /// process is killed.
+    journal: Option<PathBuf>,
+    /// Whether the files have been left modified by
+    /// `cargo minimal-versions prepare`.
+    prepared: bool,
+}
+
+// The managers alive in the process, so that the signal handler can restore
+// their files.
+static MANAGERS: Mutex<Vec<Weak<Mutex<State>>>> = Mutex::new(Vec::new());
+
+/// Returns the states of the managers alive in the process.
+fn managers() -> Vec<Arc<Mutex<State>>> {
+    MANAGERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+/// Restores files registered to all managers alive in the process, and
+/// unregisters them.
+///
+/// Programs that own the signal handler should call this from their handler.
+pub fn restore_all() {
+    for state in managers() {
+        state.lock().unwrap_or_else(PoisonError::into_inner).restore_all();
+    }
+}
+
+/// Sets a Ctrl-C (and SIGTERM and SIGHUP) handler that terminates the running
+/// child process with the received signal, restores all registered files, and
+/// exits the process with status 128 + the signal number (e.g., 130 for
+/// SIGINT, 143 for SIGTERM).
+///
+/// Programs that own the signal handler should not call this, and call
+/// [`restore_all`] from their handler instead.
+pub fn set_signal_handler() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use signal_hook::{
+            consts::{SIGHUP, SIGINT, SIGTERM},
+            iterator::Signals,
+        };
+        let mut signals =
+            Signals::new([SIGINT, SIGTERM, SIGHUP]).context("failed to set signal handler")?;
+        std::thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                on_signal(signal);
+            }
+        });
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        // Ctrl-C, Ctrl-Break, and closing the console are all handled as SIGINT.
+        ctrlc::set_handler(|| on_signal(2)).context("failed to set signal handler")
+    }
+}
+
+fn on_signal(signal: i32) -> ! {
+    // Terminate the child process first, so that it does not modify files
+    // after they are restored.
+    process::terminate_children(signal);
+    restore_all();
+    // Remove the copy of the workspace (--isolated).
+    fs::remove_temp_dirs();
+    // 128 + signal number, as shells do.
+    std::process::exit(128 + signal)
+}
+
+/// Returns the original contents of the given path, if registered.
+///
+/// Unlike `Manager::new`, this doesn't create a manager, which restores all
+/// files when dropped.
+pub(crate) fn original(path: &Path) -> Option<Vec<u8>> {
+    managers().into_iter().find_map(|state| {
+        let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.files.iter().find(|file| file.path == path).and_then(|file| file.contents.clone())
+    })
+}
+
+/// Returns the journal directory for the given target directory.
+pub(crate) fn journal_dir(target_dir: &Path) -> PathBuf {
+    target_dir.join("minimal-versions/backup")
+}
+
+/// Returns the path to the lock file that prevents concurrent runs in the
+/// same workspace.
+pub(crate) fn lock_path(target_dir: &Path) -> PathBuf {
+    target_dir.join("minimal-versions.lock")
+}
+
+/// Returns whether a journal left by an interrupted run exists.
+pub(crate) fn has_journal(journal: &Path) -> bool {
+    journal.join(JOURNAL_INDEX).exists()
+}
+
+/// Returns whether the journal has been left by `cargo minimal-versions prepare`.
+pub(crate) fn is_prepared(journal: &Path) -> bool {
+    std::fs::read_to_string(journal.join(JOURNAL_INDEX))
+        .ok()
+        .and_then(|index| serde_json::from_str::<Value>(&index).ok())
+        .and_then(|index| index.get("prepared").and_then(Value::as_bool))
+        .unwrap_or(false)
+}
+
+/// Leaves all registered files modified, and keeps the journal so that they
+/// can be restored by `cargo minimal-versions restore`
+/// (`cargo minimal-versions prepare`).
+pub(crate) fn keep_all() -> Result<()> {
+    for state in managers() {
+        state.lock().unwrap_or_else(PoisonError::into_inner).keep_all()?;
+    }
+    Ok(())
+}
+
+/// Restores files recorded in the journal, and removes the journal
+/// (`cargo minimal-versions restore`).
+pub(crate) fn replay(journal: &Path) -> Result<()> {
+    if !has_journal(journal) {
+        info!("nothing to restore; no backup found in {}", journal.display());
+        return Ok(());
+    }
+    let index = &journal.join(JOURNAL_INDEX);
+    let index: Value = serde_json::from_str(&fs::read_to_string(index)?)
+        .with_context(|| format!("failed to parse {}", index.display()))?;
+    let entries = index
+        .get("files")
+        .and_then(Value::as_array)
+        .ok_or_else(|| format_err!("failed to parse `files` field from journal"))?;
+    for entry in entries {
+        let Some(path) = entry.get("path").and_then(Value::as_str) else {
+            bail!("failed to parse `files` field from journal");
+        };
+        // `null` backup means that the file didn't exist before the run.
+        let backup = match entry.get("backup") {
+            None | Some(Value::Null) => None,
+            Some(v) => Some(
+                v.as_str()
+                    .ok_or_else(|| format_err!("failed to parse `backup` field from journal"))?,
+            ),
+        };
+        let written = match entry.get("written") {
+            None | Some(Value::Null) => None,
+            Some(v) => Some(
+                v.as_str()
+                    .ok_or_else(|| format_err!("failed to parse `written` field from journal"))?,
+            ),
+        };
+        let mtime = match entry.get("mtime") {
+            None | Some(Value::Null) => None,
+            Some(v) => Some(
+                v.as_array()
+                    .and_then(|v| match &**v {
+                        [secs, nanos] => {
+                            Some(Duration::new(secs.as_u64()?, nanos.as_u64()?.try_into().ok()?))
+                        }
+                        _ => None,
+                    })
+                    .map(|d| SystemTime::UNIX_EPOCH + d)
+                    .ok_or_else(|| format_err!("failed to parse `mtime` field from journal"))?,
+            ),
+        };
+        info!("restoring {path}");
+        let contents = backup.map(|backup| fs::read(journal.join(backup))).transpose()?;
+        let written = written.map(|written| fs::read(journal.join(written))).transpose()?;
+        File { contents, path: path.into(), mtime, written }.restore()?;
+    }
+    fs::remove_dir_all(journal)
 }
 
 impl Manager {
-    pub(crate) fn new() -> Self {
-        let this = Self { files: Arc::new(Mutex::new(vec![])) };
+    /// Returns a new manager with no files registered.
+    #[must_use]
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let mut managers = MANAGERS.lock().unwrap_or_else(PoisonError::into_inner);
+        managers.retain(|state| state.strong_count() != 0);
+        managers.push(Arc::downgrade(&state));
+        Self { state }
+    }
+
+    /// Writes the original contents of files registered after this call to
+    /// the given directory.
+    pub(crate) fn set_journal(&self, journal: PathBuf) -> Result<()> {
+        if is_prepared(&journal) {
+            bail!(
+                "files in this workspace have been modified by `cargo minimal-versions prepare`; \
+                 run `cargo minimal-versions restore` first"
+            );
+        }
+        if has_journal(&journal) {
+            bail!(
+                "refusing to overwrite backup of files modified by an interrupted run in {}; \
+                 run `cargo minimal-versions restore` first",
+                journal.display()
+            );
+        }
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.journal = Some(journal);
+        Ok(())
+    }
 
-        let cloned = this.clone();
-        ctrlc::set_handler(move || {
-            cloned.restore_all();
-            std::process::exit(1)
-        })
-        .unwrap();
+    /// Registers the given path with its original contents.
+    pub fn register(&self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) -> Result<()> {
+        let path = path.into();
+        let mtime = fs::modified(&path);
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.files.push(File { contents: Some(contents.into()), path, mtime, written: None });
+        state.write_journal()
+    }
 
-        this
+    /// Registers the given path that doesn't exist yet, so that it will be
+    /// removed if created while running.
+    pub fn register_created(&self, path: impl Into<PathBuf>) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.files.push(File { contents: None, path: path.into(), mtime: None, written: None });
+        state.write_journal()
     }
 
-    /// Registers the given path.
-    pub(crate) fn register(&self, contents: impl Into<Vec<u8>>, path: impl Into<PathBuf>) {
-        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
-        files.push(File { contents: contents.into(), path: path.into() });
+    /// Registers the given path with its original contents, and then writes
+    /// `new` to it.
+    ///
+    /// Unlike `register`, `new` is also recorded, to detect changes
+    /// made to the file by others while running. If `new` is the same as the
+    /// original contents, the file is left untouched.
+    pub fn write(&self, path: &Path, orig: impl Into<Vec<u8>>, new: &[u8]) -> Result<()> {
+        let orig = orig.into();
+        if orig == new {
+            return Ok(());
+        }
+        {
+            let mtime = fs::modified(path);
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.files.push(File {
+                contents: Some(orig),
+                path: path.to_owned(),
+                mtime,
+                written: Some(new.to_owned()),
+            });
+            state.write_journal()?;
+        }
+        fs::write_atomic(path, new, None)
     }
 
-    pub(crate) fn restore_all(&self) {
-        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
-        if !files.is_empty() {
-            for file in mem::take(&mut *files) {
-                if let Err(e) = file.restore() {
-                    error!("{e:#}");
-                }
-            }
+    /// Unregisters the given path, so that it will not be restored.
+    pub fn forget(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.files.retain(|file| file.path != path);
+        if let Err(e) = state.write_journal() {
+            warn!("{e:#}");
         }
     }
+
+    /// Restores all files registered to this manager, and unregisters them.
+    pub fn restore_all(&self) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).restore_all();
+    }
+}
+
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Drop for Manager {
@@ -56,19 +308,255 @@ impl Drop for Manager {
     }
 }
 
+impl State {
+    fn restore_all(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        let mut restored = true;
+        for file in mem::take(&mut self.files) {
+            if let Err(e) = file.restore() {
+                error!("{e:#}");
+                restored = false;
+            }
+        }
+        // Keep the journal to allow retrying by `cargo minimal-versions restore`.
+        if restored {
+            if let Some(journal) = &self.journal {
+                if let Err(e) = fs::remove_dir_all(journal) {
+                    warn!("{e:#}");
+                }
+            }
+        }
+    }
+
+    fn keep_all(&mut self) -> Result<()> {
+        self.prepared = true;
+        self.write_journal()?;
+        self.files.clear();
+        self.journal = None;
+        Ok(())
+    }
+
+    fn write_journal(&self) -> Result<()> {
+        let Some(journal) = &self.journal else { return Ok(()) };
+        if self.files.is_empty() {
+            if journal.exists() {
+                fs::remove_dir_all(journal)?;
+            }
+            return Ok(());
+        }
+        fs::create_dir_all(journal)?;
+        let mut entries = Vec::with_capacity(self.files.len());
+        for (i, file) in self.files.iter().enumerate() {
+            let Some(path) = file.path.to_str() else {
+                bail!("non-UTF-8 path is not supported: {}", file.path.display());
+            };
+            let backup = match &file.contents {
+                Some(contents) => {
+                    let backup = i.to_string();
+                    fs::write(journal.join(&backup), contents)?;
+                    Some(backup)
+                }
+                None => None,
+            };
+            let written = match &file.written {
+                Some(written) => {
+                    let backup = format!("{i}.written");
+                    fs::write(journal.join(&backup), written)?;
+                    Some(backup)
+                }
+                None => None,
+            };
+            let mtime = file
+                .mtime
+                .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| [d.as_secs(), d.subsec_nanos().into()]);
+            entries.push(json!({
+                "path": path,
+                "backup": backup,
+                "mtime": mtime,
+                "written": written,
+            }));
+        }
+        // Write the index last, so that it only refers to complete backups.
+        let index = json!({ "files": entries, "prepared": self.prepared });
+        fs::write(journal.join(JOURNAL_INDEX), index.to_string())
+    }
+}
+
 struct File {
-    /// The original contents of this file.
-    contents: Vec<u8>,
+    /// The original contents of this file, or `None` if this file didn't exist.
+    contents: Option<Vec<u8>>,
     /// Path to this file.
     path: PathBuf,
+    /// The original modification time of this file, restored with the
+    /// contents to avoid unnecessary rebuilds.
+    mtime: Option<SystemTime>,
+    /// The contents written by us, if any.
+    written: Option<Vec<u8>>,
 }
 
 impl File {
     fn restore(self) -> Result<()> {
         if term::verbose() {
-            info!("restoring {}", self.path.display());
+            if self.contents.is_some() {
+                info!("restoring {}", self.path.display());
+            } else {
+                info!("removing {}", self.path.display());
+            }
         }
-        fs::write(&self.path, &self.contents)?;
-        Ok(())
+        let Some(contents) = &self.contents else {
+            // Remove the file created while running.
+            return fs::remove_file_if_exists(&self.path);
+        };
+        let current = std::fs::read(&self.path).ok();
+        if let (Some(written), Some(current)) = (&self.written, &current) {
+            // Do not lose changes made to the file by others while running.
+            if current != written {
+                let user = save_user_version(&self.path, current)?;
+                warn!(
+                    "{} was modified while cargo-minimal-versions was running; \
+                     the modified version has been saved to {} and the original version \
+                     has been restored",
+                    self.path.display(),
+                    user.display()
+                );
+            }
+        }
+        if current.as_ref() == Some(contents) {
+            // Skip rewriting the file with the same contents, but put back
+            // the modification time in case it was rewritten while running.
+            if let Some(mtime) = self.mtime {
+                if fs::modified(&self.path) != Some(mtime) {
+                    fs::set_modified(&self.path, mtime)?;
+                }
+            }
+            return Ok(());
+        }
+        fs::write_atomic(&self.path, contents, self.mtime)
+    }
+}
+
+/// Writes the version of the given file modified by others while running to
+/// `<path>.user`, or `<path>.user.<N>` if it already exists, and returns the
+/// path written to.
+///
+/// Existing files are never overwritten, so the version saved by a previous
+/// run is not lost.
+fn save_user_version(path: &Path, contents: &[u8]) -> Result<PathBuf> {
+    for i in 0.. {
+        let mut user = path.as_os_str().to_owned();
+        user.push(".user");
+        if i != 0 {
+            user.push(format!(".{i}"));
+        }
+        let user = PathBuf::from(user);
+        match std::fs::File::create_new(&user) {
+            Ok(mut file) => {
+                io::Write::write_all(&mut file, contents)
+                    .with_context(|| format!("failed to write to file `{}`", user.display()))?;
+                return Ok(user);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to create file `{}`", user.display()));
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Manager, has_journal, is_prepared, original, replay, save_user_version};
+    use crate::fs::test_dir;
+
+    #[test]
+    fn managers_are_independent() {
+        let tmp = test_dir();
+        let dir = tmp.path();
+        let a = &dir.join("a");
+        let b = &dir.join("b");
+        std::fs::write(a, "a").unwrap();
+        std::fs::write(b, "b").unwrap();
+
+        let manager = Manager::new();
+        manager.write(a, "a", b"a2").unwrap();
+        {
+            let temp = Manager::new();
+            temp.write(b, "b", b"b2").unwrap();
+            assert_eq!(original(a).unwrap(), b"a");
+            assert_eq!(original(b).unwrap(), b"b");
+        }
+        // Dropping another manager only restores the files registered to it.
+        assert_eq!(std::fs::read(a).unwrap(), b"a2");
+        assert_eq!(std::fs::read(b).unwrap(), b"b");
+        assert!(original(b).is_none());
+        drop(manager);
+        assert_eq!(std::fs::read(a).unwrap(), b"a");
+    }
+
+    #[test]
+    fn user_version_is_not_overwritten() {
+        let tmp = test_dir();
+        let dir = tmp.path();
+        let path = &dir.join("Cargo.toml");
+        std::fs::write(path, "orig").unwrap();
+
+        for (contents, expected) in
+            [("a", "Cargo.toml.user"), ("b", "Cargo.toml.user.1"), ("c", "Cargo.toml.user.2")]
+        {
+            let manager = Manager::new();
+            manager.write(path, "orig", b"new").unwrap();
+            std::fs::write(path, contents).unwrap();
+            drop(manager);
+            assert_eq!(std::fs::read(path).unwrap(), b"orig");
+            assert_eq!(std::fs::read(dir.join(expected)).unwrap(), contents.as_bytes());
+        }
+        // The versions saved by previous runs are kept.
+        assert_eq!(std::fs::read(dir.join("Cargo.toml.user")).unwrap(), b"a");
+        assert_eq!(save_user_version(path, b"d").unwrap(), dir.join("Cargo.toml.user.3"));
+    }
+
+    #[test]
+    fn replay_journal() {
+        let tmp = test_dir();
+        let dir = tmp.path();
+        let journal = &dir.join("backup");
+        let modified = &dir.join("Cargo.toml");
+        let created = &dir.join("Cargo.lock");
+        let kept = &dir.join("README.md");
+        std::fs::write(modified, "orig").unwrap();
+        std::fs::write(kept, "orig").unwrap();
+
+        let manager = Manager::new();
+        manager.set_journal(journal.clone()).unwrap();
+        manager.write(modified, "orig", b"new").unwrap();
+        manager.register_created(created).unwrap();
+        manager.register(kept, "orig").unwrap();
+        std::fs::write(created, "lock").unwrap();
+        std::fs::write(kept, "modified by others").unwrap();
+        manager.forget(kept);
+        // Leave the files modified as `cargo minimal-versions prepare` does.
+        // (`keep_all` is not used here, since it affects all managers in the
+        // process, including those of other tests.)
+        manager.state.lock().unwrap().keep_all().unwrap();
+        drop(manager);
+        assert!(has_journal(journal));
+        assert!(is_prepared(journal));
+        assert_eq!(std::fs::read(modified).unwrap(), b"new");
+        std::fs::write(modified, "changed after prepare").unwrap();
+
+        replay(journal).unwrap();
+        assert_eq!(std::fs::read(modified).unwrap(), b"orig");
+        assert_eq!(std::fs::read(dir.join("Cargo.toml.user")).unwrap(), b"changed after prepare");
+        assert!(!created.exists());
+        // Forgotten files are not recorded in the journal.
+        assert_eq!(std::fs::read(kept).unwrap(), b"modified by others");
+        assert!(!journal.exists());
+        // Replaying again is no-op.
+        replay(journal).unwrap();
     }
 }
@@ -0,0 +1,522 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Runs the build step for each package, like cargo-hack does.
+//
+// Due to cargo's feature integration, it is not correct to run `cargo check` or
+// `cargo build` with `-p` (`--package`) or `--workspace` (`--all`) or on virtual
+// manifest. So, we run the given subcommand once per package with its own
+// `--manifest-path`.
+
+use std::{env, path::Path};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    cargo::Workspace,
+    cli::Args,
+    manifest::Manifest,
+    metadata::{Metadata, Package, PackageId},
+    term,
+};
+
+// Flags that are only understood by cargo-hack. If any of them is passed, the
+// build step is delegated to cargo-hack.
+// https://github.com/taiki-e/cargo-hack#usage
+const CARGO_HACK_FLAGS: &[&str] = &[
+    "--each-feature",
+    "--feature-powerset",
+    "--optional-deps",
+    "--skip",
+    "--exclude-features",
+    "--include-features",
+    "--exclude-no-default-features",
+    "--exclude-all-features",
+    "--depth",
+    "--group-features",
+    "--mutually-exclusive-features",
+    "--at-least-one-of",
+    "--must-have-and-exclude-feature",
+    "--include-deps-features",
+    "--ignore-unknown-features",
+    "--version-range",
+    "--version-step",
+    "--clean-per-run",
+    "--clean-per-version",
+    "--keep-going",
+    "--partition",
+    "--log-group",
+    "--print-command-list",
+    "--no-manifest-path",
+];
+
+pub(crate) fn run(ws: &Workspace, args: &Args) -> Result<()> {
+    run_packages(ws, args, None)
+}
+
+/// Runs the build step on the given packages, or on the packages selected by
+/// `args` if `packages` is `None`.
+pub(crate) fn run_packages(
+    ws: &Workspace,
+    args: &Args,
+    packages: Option<&[PackageId]>,
+) -> Result<()> {
+    if let Some(reason) = cargo_hack_reason(args) {
+        let mut cargo = ws.cargo();
+        cargo.arg("hack");
+        cargo.args(&args.cargo_args);
+        ws.set_lockfile(&mut cargo)?;
+        if let Some(path) = &args.manifest_path {
+            cargo.arg("--manifest-path");
+            cargo.arg(path);
+        }
+        if let Some(packages) = packages {
+            for &id in packages {
+                cargo.arg("--package");
+                cargo.arg(&ws.metadata[id].name);
+            }
+        } else {
+            for spec in &args.package {
+                cargo.arg("--package");
+                cargo.arg(spec);
+            }
+            if args.workspace {
+                cargo.arg("--workspace");
+            }
+            for spec in &args.exclude {
+                cargo.arg("--exclude");
+                cargo.arg(spec);
+            }
+        }
+        if args.rust_version {
+            cargo.arg("--rust-version");
+        }
+        if !args.rest.is_empty() {
+            cargo.arg("--");
+            cargo.args(&args.rest);
+        }
+        if term::verbose() {
+            info!("using cargo-hack because {reason} is only supported by cargo-hack");
+        }
+        if args.dry_run {
+            cargo.display_env_vars();
+            println!("{cargo:#}");
+            return Ok(());
+        }
+        info!("running {cargo}");
+        return cargo.run();
+    }
+
+    let mut packages = match packages {
+        Some(packages) => packages.to_vec(),
+        None => self::packages(&ws.metadata, args)?,
+    };
+    if args.rust_version {
+        for &id in &packages {
+            if ws.metadata[id].rust_version.is_none() {
+                warn!(
+                    "no rust-version field in {}'s Cargo.toml; using the current toolchain",
+                    ws.metadata[id].name
+                );
+            }
+        }
+        sort_by_rust_version(&ws.metadata, &mut packages);
+    }
+    let total = packages.len();
+    for (i, id) in packages.into_iter().enumerate() {
+        let package = &ws.metadata[id];
+        let mut cargo = match &package.rust_version {
+            Some(rust_version) if args.rust_version => ws.cargo_toolchain(rust_version),
+            _ => ws.cargo(),
+        };
+        cargo.args(&args.cargo_args);
+        ws.set_lockfile(&mut cargo)?;
+        cargo.arg("--manifest-path");
+        cargo.arg(&*package.manifest_path);
+        if !args.rest.is_empty() {
+            cargo.arg("--");
+            cargo.args(&args.rest);
+        }
+        if args.dry_run {
+            cargo.display_env_vars();
+            println!("{cargo:#}");
+            continue;
+        }
+        info!("running {cargo} on {} ({}/{total})", package.name, i + 1);
+        cargo.run()?;
+    }
+    Ok(())
+}
+
+/// Sorts packages by rust-version, so that each toolchain is used in a row
+/// (--rust-version).
+///
+/// Packages are ordered from the oldest rust-version, and packages without
+/// rust-version, which use the current toolchain, come last. The order of
+/// packages with the same rust-version is kept.
+fn sort_by_rust_version(metadata: &Metadata, packages: &mut [PackageId]) {
+    packages.sort_by_cached_key(|&id| {
+        let rust_version = metadata[id].rust_version.as_deref();
+        let version: Option<Vec<u64>> =
+            rust_version.map(|v| v.split('.').map(|n| n.parse().unwrap_or(u64::MAX)).collect());
+        (version.is_none(), version)
+    });
+}
+
+/// Returns why the build step needs to be delegated to cargo-hack, if needed.
+fn cargo_hack_reason(args: &Args) -> Option<String> {
+    let flag = args.cargo_args.iter().find_map(|a| {
+        CARGO_HACK_FLAGS.iter().copied().find(|&flag| {
+            a.strip_prefix(flag).is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+        })
+    });
+    if let Some(flag) = flag {
+        return Some(format!("{flag} flag"));
+    }
+    let spec = args.package.iter().chain(&args.exclude).find(|s| Spec::parse(s).is_none())?;
+    Some(format!("package ID specification `{spec}`"))
+}
+
+/// A package ID specification or a glob pattern passed to `-p` or `--exclude`.
+enum Spec<'a> {
+    /// `<NAME>`, `<NAME>@<VERSION>`, or `<NAME>:<VERSION>`, where `<VERSION>`
+    /// may be partial (e.g., `1` or `1.2`).
+    Name(&'a str, Option<&'a str>),
+    /// A glob pattern of package names with `*` or `?`.
+    Glob(&'a str),
+}
+
+impl<'a> Spec<'a> {
+    /// Parses the given spec, or returns `None` if it is only supported by
+    /// cargo-hack (e.g., URL forms, or glob patterns with `[...]`).
+    fn parse(spec: &'a str) -> Option<Self> {
+        if spec.contains(['/', '#', '[', ']']) {
+            return None;
+        }
+        if spec.contains(['*', '?']) {
+            return Some(Self::Glob(spec));
+        }
+        match spec.split_once(['@', ':']) {
+            Some((name, version)) => Some(Self::Name(name, Some(version))),
+            None => Some(Self::Name(spec, None)),
+        }
+    }
+
+    fn matches(&self, package: &Package) -> bool {
+        match *self {
+            Self::Name(name, version) => {
+                package.name == name && version.is_none_or(|v| version_matches(&package.version, v))
+            }
+            Self::Glob(pattern) => glob_matches(pattern, &package.name),
+        }
+    }
+}
+
+/// Returns whether `version` matches the given full or partial version.
+fn version_matches(version: &str, partial: &str) -> bool {
+    let Ok(version) = version.parse::<semver::Version>() else { return false };
+    if let Ok(full) = partial.parse::<semver::Version>() {
+        return version == full;
+    }
+    let mut parts = partial.split('.');
+    parts.next().and_then(|major| major.parse().ok()) == Some(version.major)
+        && match parts.next() {
+            None => true,
+            Some(minor) => minor.parse().ok() == Some(version.minor) && parts.next().is_none(),
+        }
+}
+
+/// Returns whether `name` matches the glob pattern with `*` and `?`.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // The position of the last `*` in the pattern, and the position in the
+    // name matched with it.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character.
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Determines the packages to run the build step on, in the same way as cargo.
+pub(crate) fn packages(metadata: &Metadata, args: &Args) -> Result<Vec<PackageId>> {
+    let current_manifest = match &args.manifest_path {
+        Some(path) => Some(Path::new(path).to_owned()),
+        None => env::current_dir()?
+            .ancestors()
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|manifest_path| manifest_path.is_file()),
+    };
+    select_packages(metadata, args, current_manifest.as_deref())
+}
+
+fn select_packages(
+    metadata: &Metadata,
+    args: &Args,
+    current_manifest: Option<&Path>,
+) -> Result<Vec<PackageId>> {
+    // Private and skipped members are removed from the workspace by manifest::with.
+    let is_removed = |id: PackageId| -> Result<bool> {
+        Ok(args.members.get(&metadata[id].name).is_some_and(|m| m.skip)
+            || args.no_private && is_private(metadata, id)?)
+    };
+    let mut packages = vec![];
+    if args.workspace {
+        let mut exclude = Vec::with_capacity(args.exclude.len());
+        for spec in &args.exclude {
+            let parsed = parse_spec(spec)?;
+            if !metadata.workspace_members.iter().any(|&id| parsed.matches(&metadata[id])) {
+                warn!("excluded package(s) `{spec}` not found in workspace");
+            }
+            exclude.push(parsed);
+        }
+        packages.extend(
+            metadata
+                .workspace_members
+                .iter()
+                .filter(|&&id| !exclude.iter().any(|spec| spec.matches(&metadata[id]))),
+        );
+    } else if !args.package.is_empty() {
+        for spec in &args.package {
+            let parsed = parse_spec(spec)?;
+            let mut matched = false;
+            for &id in &*metadata.workspace_members {
+                if parsed.matches(&metadata[id]) {
+                    matched = true;
+                    if !packages.contains(&id) {
+                        packages.push(id);
+                    }
+                }
+            }
+            if !matched {
+                match parsed {
+                    Spec::Glob(..) => bail!("package pattern(s) `{spec}` not found in workspace"),
+                    Spec::Name(..) => {
+                        bail!("package ID specification `{spec}` did not match any packages");
+                    }
+                }
+            }
+        }
+    } else {
+        let current_package = current_manifest.and_then(|current_manifest| {
+            metadata.workspace_members.iter().copied().find(|&id| {
+                same_file::is_same_file(current_manifest, &metadata[id].manifest_path)
+                    .unwrap_or(false)
+            })
+        });
+        let root_manifest = &metadata.workspace_root.join("Cargo.toml");
+        match current_package {
+            // The root manifest is made virtual by manifest::with if the root
+            // package is removed from the workspace.
+            Some(id) if *metadata[id].manifest_path == **root_manifest && is_removed(id)? => {
+                packages.extend(metadata.workspace_members.iter());
+            }
+            Some(id) => packages.push(id),
+            // Virtual manifest.
+            None => packages.extend(metadata.workspace_members.iter()),
+        }
+    }
+    let mut remaining = Vec::with_capacity(packages.len());
+    for id in packages {
+        if !is_removed(id)? {
+            remaining.push(id);
+        }
+    }
+    let packages = remaining;
+    if packages.is_empty() {
+        bail!("no packages to run the build step on");
+    }
+    Ok(packages)
+}
+
+fn parse_spec(spec: &str) -> Result<Spec<'_>> {
+    match Spec::parse(spec) {
+        Some(spec) => Ok(spec),
+        None => bail!(
+            "package ID specification `{spec}` is only supported when the build step is run \
+             by cargo-hack; use the package name, `<NAME>@<VERSION>`, or a glob pattern with \
+             `*` or `?` instead"
+        ),
+    }
+}
+
+fn is_private(metadata: &Metadata, id: PackageId) -> Result<bool> {
+    let package = &metadata[id];
+    Ok(if metadata.cargo_version >= 39 {
+        !package.publish
+    } else {
+        !Manifest::new(&package.manifest_path, metadata.cargo_version)?.package.publish.unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::{Value, json};
+
+    use super::{glob_matches, select_packages, sort_by_rust_version};
+    use crate::{cli::Args, fs::test_dir, metadata::Metadata};
+
+    fn package(name: &str, dir: &Path, publish: bool) -> Value {
+        package_with_version(name, "0.1.0", dir, publish)
+    }
+
+    fn package_with_version(name: &str, version: &str, dir: &Path, publish: bool) -> Value {
+        let manifest_path = dir.join("Cargo.toml");
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(&manifest_path, "").unwrap();
+        json!({
+            "id": name,
+            "name": name,
+            "version": version,
+            "manifest_path": manifest_path,
+            "publish": if publish { Value::Null } else { json!([]) },
+            "dependencies": [],
+            "rust_version": null,
+        })
+    }
+
+    fn names(metadata: &Metadata, args: &[&str], current_manifest: &Path) -> Vec<String> {
+        let args = Args::parse_from(
+            ["cargo", "minimal-versions", "check"].into_iter().chain(args.iter().copied()),
+        )
+        .unwrap()
+        .unwrap();
+        select_packages(metadata, &args, Some(current_manifest))
+            .unwrap()
+            .into_iter()
+            .map(|id| metadata[id].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn private_root() {
+        let tmp = test_dir();
+        let root = tmp.path();
+        let metadata = Metadata::from_json(&json!({
+            "packages": [
+                package("root", root, false),
+                package("a", &root.join("a"), true),
+                package("b", &root.join("b"), false),
+            ],
+            "workspace_members": ["root", "a", "b"],
+            "workspace_root": root,
+            "target_directory": root.join("target"),
+        }));
+        let root_manifest = &root.join("Cargo.toml");
+        let a_manifest = &root.join("a/Cargo.toml");
+
+        assert_eq!(names(&metadata, &[], root_manifest), ["root"]);
+        assert_eq!(names(&metadata, &["--workspace"], root_manifest), ["root", "a", "b"]);
+        // The root manifest is made virtual, so all remaining members are selected.
+        assert_eq!(names(&metadata, &["--ignore-private"], root_manifest), ["a"]);
+        assert_eq!(names(&metadata, &["--ignore-private"], a_manifest), ["a"]);
+        assert_eq!(names(&metadata, &["--ignore-private", "--workspace"], root_manifest), ["a"]);
+    }
+
+    #[test]
+    fn rust_version_groups() {
+        let tmp = test_dir();
+        let root = tmp.path();
+        let package = |name: &str, rust_version: Option<&str>| {
+            let mut package = package(name, &root.join(name), true);
+            package["rust_version"] = json!(rust_version);
+            package
+        };
+        let metadata = Metadata::from_json(&json!({
+            "packages": [
+                package("a", None),
+                package("b", Some("1.70")),
+                package("c", Some("1.9")),
+                package("d", None),
+                package("e", Some("1.70")),
+                package("f", Some("1.70.1")),
+                package("g", Some("1.56")),
+            ],
+            "workspace_members": ["a", "b", "c", "d", "e", "f", "g"],
+            "workspace_root": root,
+            "target_directory": root.join("target"),
+        }));
+        let mut packages = metadata.workspace_members.clone();
+        sort_by_rust_version(&metadata, &mut packages);
+        let names: Vec<_> = packages.iter().map(|&id| &*metadata[id].name).collect();
+        // Versions are compared numerically, and packages without rust-version
+        // come last in the original order.
+        assert_eq!(names, ["c", "g", "b", "e", "f", "a", "d"]);
+    }
+
+    #[test]
+    fn specs() {
+        let tmp = test_dir();
+        let root = tmp.path();
+        let metadata = Metadata::from_json(&json!({
+            "packages": [
+                package_with_version("foo-a", "0.1.0", &root.join("a"), true),
+                package_with_version("foo-b", "0.2.0", &root.join("b"), true),
+                package_with_version("bar", "1.2.3", &root.join("c"), true),
+            ],
+            "workspace_members": ["foo-a", "foo-b", "bar"],
+            "workspace_root": root,
+            "target_directory": root.join("target"),
+        }));
+        let current = &root.join("Cargo.toml");
+        let select = |args: &[&str]| {
+            let args = Args::parse_from(
+                ["cargo", "minimal-versions", "check"].into_iter().chain(args.iter().copied()),
+            )
+            .unwrap()
+            .unwrap();
+            select_packages(&metadata, &args, Some(current)).map(|packages| {
+                packages.into_iter().map(|id| metadata[id].name.clone()).collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(select(&["-p", "bar@1.2.3"]).unwrap(), ["bar"]);
+        assert_eq!(select(&["-p", "bar@1"]).unwrap(), ["bar"]);
+        assert_eq!(select(&["-p", "bar:1.2"]).unwrap(), ["bar"]);
+        assert!(select(&["-p", "bar@1.3"]).is_err());
+        assert!(select(&["-p", "bar@2.0.0"]).is_err());
+        assert_eq!(select(&["-p", "foo-*", "-p", "foo-a"]).unwrap(), ["foo-a", "foo-b"]);
+        assert!(select(&["-p", "baz-*"]).is_err());
+        assert_eq!(select(&["--workspace", "--exclude", "foo-*"]).unwrap(), ["bar"]);
+        assert_eq!(select(&["--workspace", "--exclude", "foo-?"]).unwrap(), ["bar"]);
+        assert_eq!(select(&["--workspace", "--exclude", "foo?"]).unwrap(), [
+            "foo-a", "foo-b", "bar"
+        ]);
+        assert_eq!(select(&["--workspace", "--exclude", "foo-b@0.2"]).unwrap(), ["foo-a", "bar"]);
+        // URL forms are only supported by cargo-hack.
+        assert!(select(&["-p", "https://github.com/rust-lang/crates.io-index#bar@1.2.3"]).is_err());
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("foo-*", "foo-a"));
+        assert!(glob_matches("foo-*", "foo-"));
+        assert!(glob_matches("*-a", "foo-bar-a"));
+        assert!(glob_matches("f?o*b*", "foo-bar"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("foo-?", "foo-ab"));
+        assert!(!glob_matches("*-a", "foo-a-b"));
+        assert!(!glob_matches("foo", "foo-a"));
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of `cargo minimal-versions search`.
+//
+// Finds the lowest version of each direct dependency that actually compiles by
+// binary-searching the published versions that match its requirement, on top of
+// Cargo.lock with minimal versions.
+
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+use crate::{app, cargo::Workspace, cli::Args, fs, lockfile::Lockfile, process, runner, term};
+
+struct Dependency {
+    name: String,
+    req: semver::VersionReq,
+    /// Published versions that match the requirement, in ascending order.
+    candidates: Vec<semver::Version>,
+}
+
+pub(crate) fn run(ws: &Workspace, args: &Args) -> Result<()> {
+    let lockfile_path = &ws.metadata.workspace_root.join("Cargo.lock");
+    let deps = direct_deps(ws, args)?;
+
+    info!("checking the build with minimal versions");
+    if build(ws, args)? {
+        info!("the build succeeded with minimal versions; no search is needed");
+        return Ok(());
+    }
+    if deps.is_empty() {
+        bail!(
+            "the build failed with minimal versions, but there are no direct dependencies to search"
+        );
+    }
+
+    // Raise all direct dependencies to the highest versions on top of the
+    // minimal versions of other dependencies first, to search each dependency
+    // regardless of the dependencies that have not been searched yet.
+    for dep in &deps {
+        let highest = dep.candidates.last().unwrap();
+        if !precise(ws, args, lockfile_path, dep, highest)? {
+            bail!("failed to update `{}` to {highest}", dep.name);
+        }
+    }
+    info!("checking the build with the highest versions of direct dependencies");
+    if !build(ws, args)? {
+        bail!(
+            "the build failed even with the highest versions of direct dependencies; \
+             the failure is not caused by too-low version requirements of direct dependencies"
+        );
+    }
+
+    // Search each dependency on top of the lowest versions found for the
+    // previous dependencies, so that the found versions work together.
+    let mut lockfile = fs::read(lockfile_path)?;
+    let mut results = Vec::with_capacity(deps.len());
+    for dep in &deps {
+        let lowest = lowest_working(&dep.candidates, |version| {
+            write_lockfile(lockfile_path, &lockfile)?;
+            info!("checking `{}` {version}", dep.name);
+            Ok(precise(ws, args, lockfile_path, dep, version)? && build(ws, args)?)
+        })?;
+        write_lockfile(lockfile_path, &lockfile)?;
+        if !precise(ws, args, lockfile_path, dep, lowest)? {
+            bail!("failed to update `{}` to {lowest}", dep.name);
+        }
+        lockfile = fs::read(lockfile_path)?;
+        results.push(lowest);
+    }
+    info!("checking the build with the found versions of all direct dependencies");
+    if !build(ws, args)? {
+        bail!(
+            "the build failed with the found versions of all direct dependencies applied together"
+        );
+    }
+
+    print_table(&deps, &results);
+    Ok(())
+}
+
+/// Binary-searches the lowest version in `candidates` (in ascending order) for
+/// which `check` returns `true`.
+///
+/// The highest version is assumed to pass, and is not checked.
+fn lowest_working(
+    candidates: &[semver::Version],
+    mut check: impl FnMut(&semver::Version) -> Result<bool>,
+) -> Result<&semver::Version> {
+    let (mut lo, mut hi) = (0, candidates.len() - 1);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if check(&candidates[mid])? {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Ok(&candidates[hi])
+}
+
+/// Runs the build step and returns whether it succeeded.
+///
+/// Returns an error instead if the build step was interrupted by a signal.
+fn build(ws: &Workspace, args: &Args) -> Result<bool> {
+    match runner::run(ws, args) {
+        Ok(()) => Ok(true),
+        Err(e) if process::terminating() => Err(e),
+        Err(e) => {
+            if term::verbose() {
+                info!("{e:#}");
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Overwrites Cargo.lock, unless the signal handler is restoring it.
+fn write_lockfile(lockfile_path: &Path, contents: &[u8]) -> Result<()> {
+    process::unless_terminating(|| fs::write(lockfile_path, contents))
+}
+
+/// Updates the locked version of the given dependency to `version`, and
+/// returns whether it succeeded.
+///
+/// Returns an error instead if cargo was interrupted by a signal.
+fn precise(
+    ws: &Workspace,
+    args: &Args,
+    lockfile_path: &Path,
+    dep: &Dependency,
+    version: &semver::Version,
+) -> Result<bool> {
+    let lockfile = Lockfile::new(lockfile_path)?;
+    let Some(current) = lockfile.versions(&dep.name).filter(|v| dep.req.matches(v)).min() else {
+        bail!("`{}` not found in Cargo.lock", dep.name);
+    };
+    if current == version {
+        return Ok(true);
+    }
+    // Keep transitive dependencies newly required by the given version minimal.
+    let mut cargo = ws.cargo_nightly();
+    cargo.args(["update", "-Z", app::minimal_versions_flag(args.direct)]);
+    cargo.args(["-p", &format!("{}@{current}", dep.name), "--precise"]);
+    cargo.arg(version.to_string());
+    if term::verbose() {
+        info!("running {cargo}");
+    }
+    match cargo.run_with_output() {
+        Ok(_) => Ok(true),
+        Err(e) if process::terminating() => Err(e),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Collects registry dependencies in [dependencies] and [build-dependencies]
+/// of the packages to run the build step on.
+fn direct_deps(ws: &Workspace, args: &Args) -> Result<Vec<Dependency>> {
+    let lockfile = Lockfile::new(&ws.metadata.workspace_root.join("Cargo.lock"))?;
+    let cargo_home = cargo_home();
+    let mut deps: Vec<Dependency> = vec![];
+    for id in runner::packages(&ws.metadata, args)? {
+        for dep in &ws.metadata[id].dependencies {
+            if dep.kind.as_deref() == Some("dev")
+                || !dep
+                    .source
+                    .as_deref()
+                    .is_some_and(|s| s.starts_with("registry+") || s.starts_with("sparse+"))
+            {
+                continue;
+            }
+            let Ok(req) = semver::VersionReq::parse(&dep.req) else { continue };
+            if deps.iter().any(|d| d.name == dep.name && d.req == req) {
+                continue;
+            }
+            // Only versions not lower than the minimal version are candidates.
+            let Some(minimal) = lockfile.versions(&dep.name).filter(|v| req.matches(v)).min()
+            else {
+                continue;
+            };
+            let versions = match &cargo_home {
+                Some(cargo_home) => registry_versions(cargo_home, &dep.name)?,
+                None => vec![],
+            };
+            let candidates = candidates(versions, &req, minimal);
+            if candidates.is_empty() {
+                warn!(
+                    "no versions of `{}` found in the local registry index cache; skipping",
+                    dep.name
+                );
+                continue;
+            }
+            deps.push(Dependency { name: dep.name.clone(), req, candidates });
+        }
+    }
+    Ok(deps)
+}
+
+/// Returns the stable versions in `versions` that match `req` and are not lower
+/// than `minimal`, in ascending order.
+fn candidates(
+    mut versions: Vec<semver::Version>,
+    req: &semver::VersionReq,
+    minimal: &semver::Version,
+) -> Vec<semver::Version> {
+    versions.retain(|v| v.pre.is_empty() && v >= minimal && req.matches(v));
+    versions.sort_unstable();
+    versions.dedup();
+    versions
+}
+
+/// Returns the path of the given crate in the registry index.
+// https://doc.rust-lang.org/nightly/cargo/reference/registry-index.html#index-files
+fn index_path(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Returns non-yanked versions of the given crate recorded in the local
+/// registry index cache.
+fn registry_versions(cargo_home: &Path, name: &str) -> Result<Vec<semver::Version>> {
+    let path = index_path(name);
+    let mut versions = vec![];
+    let Ok(indexes) = std::fs::read_dir(cargo_home.join("registry/index")) else {
+        return Ok(versions);
+    };
+    for index in indexes {
+        let cache = index?.path().join(".cache").join(&path);
+        if !cache.is_file() {
+            continue;
+        }
+        // The cache file is a list of NUL-separated entries, in which each
+        // version is followed by its JSON entry in the index.
+        for entry in fs::read(&cache)?.split(|&b| b == 0) {
+            if !entry.starts_with(b"{") {
+                continue;
+            }
+            let Ok(Value::Object(entry)) = serde_json::from_slice(entry) else { continue };
+            if entry.get("yanked").and_then(Value::as_bool) == Some(true) {
+                continue;
+            }
+            if let Some(Ok(version)) = entry.get("vers").and_then(Value::as_str).map(str::parse) {
+                versions.push(version);
+            }
+        }
+    }
+    Ok(versions)
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("CARGO_HOME") {
+        return Some(path.into());
+    }
+    let home = if cfg!(windows) { env::var_os("USERPROFILE") } else { env::var_os("HOME") };
+    home.map(|home| Path::new(&home).join(".cargo"))
+}
+
+fn print_table(deps: &[Dependency], results: &[&semver::Version]) {
+    struct Row(String, String, String, String);
+    impl fmt::Display for Row {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:<24} {:<16} {:<18} {}", self.0, self.1, self.2, self.3)
+        }
+    }
+    println!(
+        "{}",
+        Row(
+            "DEPENDENCY".to_owned(),
+            "REQUIREMENT".to_owned(),
+            "DECLARED MINIMUM".to_owned(),
+            "REAL MINIMUM".to_owned()
+        )
+    );
+    for (dep, real) in deps.iter().zip(results) {
+        let declared = dep.candidates.first().unwrap();
+        let real = if declared == *real { real.to_string() } else { format!("{real} (raise)") };
+        println!("{}", Row(dep.name.clone(), dep.req.to_string(), declared.to_string(), real));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidates, lowest_working, registry_versions};
+    use crate::fs::test_dir;
+
+    fn versions(versions: &[&str]) -> Vec<semver::Version> {
+        versions.iter().map(|v| v.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn registry_versions_from_cache() {
+        let tmp = test_dir();
+        let cargo_home = tmp.path();
+        let write = |index: &str, path: &str, contents: &[u8]| {
+            let path = cargo_home.join("registry/index").join(index).join(".cache").join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        let entry = |vers: &str, yanked: bool| {
+            format!("{vers}\0{{\"name\":\"serde\",\"vers\":\"{vers}\",\"yanked\":{yanked}}}\0")
+        };
+        // Header, followed by NUL-separated pairs of a version and its entry.
+        let cache = format!(
+            "\x03\0\0\0etag\0{}{}{}",
+            entry("1.0.0", false),
+            entry("1.0.1", true),
+            entry("1.0.2", false)
+        );
+        write("index.crates.io-1949cf8c6b5b557f", "se/rd/serde", cache.as_bytes());
+        write("other-0123456789abcdef", "se/rd/serde", entry("1.0.3", false).as_bytes());
+        write("other-0123456789abcdef", "1/a", entry("0.1.0", false).as_bytes());
+
+        let mut serde = registry_versions(cargo_home, "Serde").unwrap();
+        serde.sort_unstable();
+        assert_eq!(serde, versions(&["1.0.0", "1.0.2", "1.0.3"]));
+        assert_eq!(registry_versions(cargo_home, "a").unwrap(), versions(&["0.1.0"]));
+        assert_eq!(registry_versions(cargo_home, "b").unwrap(), []);
+    }
+
+    #[test]
+    fn filter_candidates() {
+        let req = "^1.0.1".parse().unwrap();
+        let minimal = "1.0.2".parse().unwrap();
+        let all = versions(&["1.1.0", "1.0.0", "1.0.2", "2.0.0", "1.0.3-rc.1", "1.0.3", "1.0.2"]);
+        assert_eq!(candidates(all, &req, &minimal), versions(&["1.0.2", "1.0.3", "1.1.0"]));
+    }
+
+    #[test]
+    fn binary_search() {
+        let candidates = versions(&["1.0.0", "1.0.1", "1.0.2", "1.0.3", "1.0.4", "1.1.0"]);
+        let search = |lowest: &str| {
+            let lowest: semver::Version = lowest.parse().unwrap();
+            let mut checked = vec![];
+            let found = lowest_working(&candidates, |v| {
+                checked.push(v.to_string());
+                Ok(*v >= lowest)
+            })
+            .unwrap()
+            .to_string();
+            (found, checked)
+        };
+        let (found, checked) = search("1.0.0");
+        assert_eq!(found, "1.0.0");
+        assert_eq!(checked, ["1.0.2", "1.0.1", "1.0.0"]);
+        let (found, checked) = search("1.0.3");
+        assert_eq!(found, "1.0.3");
+        assert_eq!(checked, ["1.0.2", "1.0.4", "1.0.3"]);
+        // The highest version is not checked.
+        let (found, checked) = search("1.1.0");
+        assert_eq!(found, "1.1.0");
+        assert_eq!(checked, ["1.0.2", "1.0.4"]);
+        assert_eq!(lowest_working(&candidates[5..], |_| unreachable!()).unwrap(), &candidates[5]);
+    }
+}
@@ -2,7 +2,12 @@
 
 #![cfg(not(miri))] // Miri doesn't support std::process::Command: https://github.com/rust-lang/miri/issues/3374
 
-use std::{ffi::OsStr, path::Path, process::Command};
+use std::{
+    ffi::OsStr,
+    hash::{BuildHasher as _, Hasher as _, RandomState},
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use test_helper::cli::CommandExt as _;
 
@@ -14,6 +19,27 @@ fn cargo_minimal_versions<O: AsRef<OsStr>>(args: impl AsRef<[O]>) -> Command {
     cmd
 }
 
+/// A temporary directory that is removed when the test finishes, even if it
+/// panics.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        // RandomState is randomly seeded per instance.
+        let random = RandomState::new().build_hasher().finish();
+        let path = std::env::temp_dir()
+            .join(format!("cargo-minimal-versions-test-{}-{random:016x}", std::process::id()));
+        std::fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn help() {
     let short = cargo_minimal_versions(["-h"]).assert_success();
@@ -35,3 +61,229 @@ fn update_readme() {
     let command = "cargo minimal-versions --help";
     test_helper::doc::sync_command_output_to_markdown(path, "readme-long-help", command, new);
 }
+
+/// A workspace in a temporary directory that uses a directory source with the
+/// given crates in place of crates.io, so that the tests run offline.
+struct Fixture {
+    tmp: TempDir,
+    ws: PathBuf,
+}
+
+impl Fixture {
+    /// Creates a workspace with the given root manifest, where each crate is
+    /// `(name, version, dependencies)`.
+    fn new(crates: &[(&str, &str, &str)], manifest: &str) -> Self {
+        let tmp = TempDir::new();
+        let ws = tmp.0.join("ws");
+        let fixture = Self { tmp, ws };
+        for (name, version, deps) in crates {
+            let dir = format!("../vendor/{name}-{version}");
+            fixture.write(
+                &format!("{dir}/Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{name}\"\nversion = \"{version}\"\nedition = \"2021\"\n\n\
+                     [dependencies]\n{deps}\n"
+                ),
+            );
+            fixture.write(&format!("{dir}/src/lib.rs"), "");
+            fixture.write(&format!("{dir}/.cargo-checksum.json"), r#"{"files":{},"package":null}"#);
+        }
+        fixture.write(
+            ".cargo/config.toml",
+            &format!(
+                "[source.crates-io]\nreplace-with = \"vendored\"\n\n\
+                 [source.vendored]\ndirectory = '{}'\n",
+                fixture.tmp.0.join("vendor").display()
+            ),
+        );
+        fixture.write("Cargo.toml", manifest);
+        fixture.write("src/lib.rs", "");
+        fixture
+    }
+
+    /// Writes a file at the given path relative to the workspace root.
+    fn write(&self, path: &str, contents: &str) {
+        let path = self.ws.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn cargo_minimal_versions<O: AsRef<OsStr>>(&self, args: impl AsRef<[O]>) -> Command {
+        let mut cmd = cargo_minimal_versions(args);
+        cmd.current_dir(&self.ws);
+        cmd
+    }
+}
+
+/// Returns the version of the given package in the given lockfile.
+fn locked_version(lockfile: &Path, name: &str) -> Option<String> {
+    let lockfile = std::fs::read_to_string(lockfile).ok()?;
+    let package = lockfile
+        .split("[[package]]")
+        .find(|p| p.lines().any(|l| l == format!("name = \"{name}\"")))?;
+    package
+        .lines()
+        .find_map(|l| Some(l.strip_prefix("version = \"")?.trim_end_matches('"').to_owned()))
+}
+
+// A directory source in which bar 1.0.0 (the minimal version of the
+// dev-dependency) raises foo, and bar 1.0.1 does not. bar has fewer candidates
+// than foo, so that cargo resolves bar first.
+const TWO_PHASE_CRATES: &[(&str, &str, &str)] = &[
+    ("foo", "1.0.0", ""),
+    ("foo", "1.0.1", ""),
+    ("foo", "1.0.5", ""),
+    ("bar", "1.0.0", "foo = \"1.0.5\""),
+    ("bar", "1.0.1", "foo = \"1.0.0\""),
+];
+const TWO_PHASE_MANIFEST: &str = "[package]\nname = \"ws\"\nversion = \"0.1.0\"\n\
+                                  edition = \"2021\"\n\n[dependencies]\nfoo = \"1\"\n\n\
+                                  [dev-dependencies]\nbar = \"1\"\n";
+
+#[test]
+fn two_phase() {
+    let fixture = Fixture::new(TWO_PHASE_CRATES, TWO_PHASE_MANIFEST);
+    let lockfile = &fixture.ws.join("Cargo.lock");
+
+    fixture.cargo_minimal_versions(["test", "--keep-lockfile"]).assert_success();
+    assert_eq!(locked_version(lockfile, "foo").unwrap(), "1.0.5");
+    fixture.cargo_minimal_versions(["test", "--two-phase", "--keep-lockfile"]).assert_success();
+    assert_eq!(locked_version(lockfile, "foo").unwrap(), "1.0.0");
+    assert_eq!(locked_version(lockfile, "bar").unwrap(), "1.0.1");
+    // The temporary dev-dependency used to update bar is removed.
+    assert_eq!(std::fs::read_to_string(fixture.ws.join("Cargo.toml")).unwrap(), TWO_PHASE_MANIFEST);
+}
+
+#[test]
+fn two_phase_without_dev_deps() {
+    let fixture = Fixture::new(TWO_PHASE_CRATES, TWO_PHASE_MANIFEST);
+
+    let output = fixture.cargo_minimal_versions(["check", "--two-phase"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--two-phase is only used when dev-dependencies are needed"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn per_package_lockfile() {
+    let manifest = "[workspace]\nmembers = [\"a\", \"b\"]\ndefault-members = [\"a\"]\n\
+                    resolver = \"2\"\n";
+    let fixture = Fixture::new(&[("foo", "1.0.0", ""), ("foo", "1.0.5", "")], manifest);
+    fixture.write("../vendor/foo-1.0.5/src/lib.rs", "pub fn added_in_1_0_5() {}\n");
+    let package = |name: &str, dep: &str| {
+        format!(
+            "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\nfoo = \"{dep}\"\n"
+        )
+    };
+    // a uses an API added in foo 1.0.5 but requires only foo 1, which is
+    // hidden by the requirement of b in the shared Cargo.lock.
+    fixture.write("a/Cargo.toml", &package("a", "1"));
+    fixture.write("a/src/lib.rs", "pub use foo::added_in_1_0_5;\n");
+    fixture.write("b/Cargo.toml", &package("b", "1.0.5"));
+    fixture.write("b/src/lib.rs", "");
+
+    fixture.cargo_minimal_versions(["check", "--workspace"]).assert_success();
+    fixture.cargo_minimal_versions(["check", "-p", "b", "--per-package-lockfile"]).assert_success();
+    let output = fixture
+        .cargo_minimal_versions(["check", "--workspace", "--per-package-lockfile"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("added_in_1_0_5"), "{stderr}");
+    // The root manifest is restored after each package.
+    assert_eq!(std::fs::read_to_string(fixture.ws.join("Cargo.toml")).unwrap(), manifest);
+}
+
+#[test]
+fn prepare_and_restore() {
+    let fixture = Fixture::new(TWO_PHASE_CRATES, TWO_PHASE_MANIFEST);
+    let manifest = &fixture.ws.join("Cargo.toml");
+    let lockfile = &fixture.ws.join("Cargo.lock");
+
+    fixture.cargo_minimal_versions(["prepare", "--remove-dev-deps"]).assert_success();
+    assert!(!std::fs::read_to_string(manifest).unwrap().contains("[dev-dependencies]"));
+    assert_eq!(locked_version(lockfile, "foo").unwrap(), "1.0.0");
+    assert!(locked_version(lockfile, "bar").is_none());
+
+    // Other runs refuse to overwrite the backup of the prepared files.
+    for args in [&["check"][..], &["prepare"]] {
+        let output = fixture.cargo_minimal_versions(args).output().unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("modified by `cargo minimal-versions prepare`"), "{stderr}");
+    }
+    assert!(!std::fs::read_to_string(manifest).unwrap().contains("[dev-dependencies]"));
+
+    fixture.cargo_minimal_versions(["restore"]).assert_success();
+    assert_eq!(std::fs::read_to_string(manifest).unwrap(), TWO_PHASE_MANIFEST);
+    // Cargo.lock did not exist before prepare.
+    assert!(!lockfile.exists());
+    fixture.cargo_minimal_versions(["check"]).assert_success();
+}
+
+#[test]
+fn created_and_failed_lockfile() {
+    let manifest = "[package]\nname = \"ws\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                    [dependencies]\nfoo = \"1\"\n";
+    let fixture = Fixture::new(&[("foo", "1.0.0", ""), ("foo", "1.0.5", "")], manifest);
+    fixture.write("../vendor/foo-1.0.5/src/lib.rs", "pub fn added_in_1_0_5() {}\n");
+    let lockfile = &fixture.ws.join("Cargo.lock");
+    let failed_lockfile = &fixture.ws.join("target/minimal-versions/Cargo.lock");
+
+    // Cargo.lock created while running is removed.
+    fixture.cargo_minimal_versions(["check"]).assert_success();
+    assert!(!lockfile.exists());
+    assert!(!failed_lockfile.exists());
+
+    // Cargo.lock of a failed run is saved in the target directory.
+    fixture.write("src/lib.rs", "pub use foo::added_in_1_0_5;\n");
+    let output = fixture.cargo_minimal_versions(["check"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(!lockfile.exists());
+    assert_eq!(locked_version(failed_lockfile, "foo").unwrap(), "1.0.0");
+
+    // The original Cargo.lock is restored.
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = Command::new(cargo)
+        .args(["generate-lockfile", "--offline"])
+        .current_dir(&fixture.ws)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let orig = std::fs::read_to_string(lockfile).unwrap();
+    assert_eq!(locked_version(lockfile, "foo").unwrap(), "1.0.5");
+    std::fs::remove_file(failed_lockfile).unwrap();
+    let output = fixture.cargo_minimal_versions(["check"]).output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read_to_string(lockfile).unwrap(), orig);
+    assert_eq!(locked_version(failed_lockfile, "foo").unwrap(), "1.0.0");
+}
+
+#[test]
+fn keep_lockfile_and_lockfile_out() {
+    let manifest = "[package]\nname = \"ws\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                    [dependencies]\nfoo = \"1\"\n";
+    let fixture = Fixture::new(&[("foo", "1.0.0", ""), ("foo", "1.0.5", "")], manifest);
+    let lockfile = &fixture.ws.join("Cargo.lock");
+    let lockfile_out = &fixture.ws.join("minimal.lock");
+
+    fixture.cargo_minimal_versions(["check", "--lockfile-out", "minimal.lock"]).assert_success();
+    assert_eq!(locked_version(lockfile_out, "foo").unwrap(), "1.0.0");
+    assert!(!lockfile.exists());
+
+    fixture.cargo_minimal_versions(["check", "--keep-lockfile"]).assert_success();
+    assert_eq!(locked_version(lockfile, "foo").unwrap(), "1.0.0");
+    assert_eq!(std::fs::read(lockfile).unwrap(), std::fs::read(lockfile_out).unwrap());
+
+    // Cargo.lock of a failed run is also kept.
+    std::fs::remove_file(lockfile).unwrap();
+    fixture.write("src/lib.rs", "compile_error!(\"\");\n");
+    let output = fixture.cargo_minimal_versions(["check", "--keep-lockfile"]).output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(locked_version(lockfile, "foo").unwrap(), "1.0.0");
+}